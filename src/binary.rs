@@ -1,4 +1,4 @@
-use super::{BinaryHandler, StackItem};
+use super::{BinaryHandler, StackItem, complex, restore_operands};
 
 pub fn log_op(a: f64, b: f64) -> f64 {
     a.log(b)
@@ -9,31 +9,57 @@ pub fn percent_change(a: f64, b: f64) -> f64 {
 pub fn power_op(a: f64, b: f64) -> f64 {
     a.powf(b)
 }
+/// b - a: subtraction with the operands reversed.
+pub fn reverse_sub(a: f64, b: f64) -> f64 {
+    b - a
+}
+/// b / a: division with the operands reversed.
+pub fn reverse_div(a: f64, b: f64) -> f64 {
+    b / a
+}
+/// b^a: power with the operands reversed.
+pub fn reverse_pow(a: f64, b: f64) -> f64 {
+    b.powf(a)
+}
 pub fn atan2_op(y: f64, x: f64) -> f64 {
     y.atan2(x)
 }
 
+/// Three-way comparison: -1, 0 or 1 as a is below, equal to or above b.
+/// Uses `f64::total_cmp`, so -0 < 0 and NaN sorts above +inf.
+pub fn compare(a: f64, b: f64) -> f64 {
+    a.total_cmp(&b) as i8 as f64
+}
+
+/// atan2 with the result in degrees (used in degree angle mode).
+pub fn atan2_degrees(y: f64, x: f64) -> f64 {
+    y.atan2(x).to_degrees()
+}
+
 /// Binary function for two-operand operations (e.g., +, -, *, /).
 /// Pops two numbers (a and b), applies the function (a op b), and pushes the result.
+/// If either operand is complex, the complex form of `op_symbol` is used instead.
 pub fn calculate(
     stack: &mut Vec<StackItem>,
     op: BinaryHandler,
-    _op_symbol: &str,
+    op_symbol: &str,
 ) -> Result<(), &'static str> {
     // RPN needs two operands: pop the second-to-last (b) and last (a)
     let b = match stack.pop() {
-        Some(StackItem::Number(val)) => val,
-        _ => {
+        Some(item @ (StackItem::Number(_) | StackItem::Complex(..))) => item,
+        item => {
+            restore_operands(stack, item.as_slice());
             return Err(
                 "Binary operation requires two numbers on the stack (missing second operand)",
             );
         }
     };
     let a = match stack.pop() {
-        Some(StackItem::Number(val)) => val,
-        _ => {
-            // Push the second operand back before erroring
-            stack.push(StackItem::Number(b));
+        Some(item @ (StackItem::Number(_) | StackItem::Complex(..))) => item,
+        item => {
+            // Push the operands back before erroring
+            restore_operands(stack, item.as_slice());
+            restore_operands(stack, &[b]);
             return Err(
                 "Binary operation requires two numbers on the stack (missing first operand)",
             );
@@ -41,6 +67,27 @@ pub fn calculate(
     };
 
     // Perform the calculation and push the result
-    stack.push(StackItem::Number(op(a, b)));
-    Ok(())
+    let result = match (&a, &b) {
+        (StackItem::Number(x), StackItem::Number(y)) => Some(StackItem::Number(op(*x, *y))),
+        _ => complex::binary(op_symbol, as_complex(&a), as_complex(&b)).map(complex::normalize),
+    };
+    match result {
+        Some(item) => {
+            stack.push(item);
+            Ok(())
+        }
+        None => {
+            restore_operands(stack, &[a, b]);
+            Err("Operator does not support complex numbers")
+        }
+    }
+}
+
+/// Reads a numeric stack item as (real, imaginary) parts.
+fn as_complex(item: &StackItem) -> (f64, f64) {
+    match item {
+        StackItem::Complex(re, im) => (*re, *im),
+        StackItem::Number(val) => (*val, 0.0),
+        StackItem::Key(_) => (f64::NAN, f64::NAN),
+    }
 }
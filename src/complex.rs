@@ -0,0 +1,92 @@
+use super::StackItem;
+
+/// A complex value as (real, imaginary) parts.
+type Complex = (f64, f64);
+
+/// Converts a complex result back into a stack item.
+/// Results with an exactly zero imaginary part collapse to a plain number.
+pub fn normalize((re, im): Complex) -> StackItem {
+    if im == 0.0 {
+        StackItem::Number(re)
+    } else {
+        StackItem::Complex(re, im)
+    }
+}
+
+/// Formats a complex value as `a+bi` / `a-bi`.
+pub fn format((re, im): Complex) -> String {
+    if im.is_sign_negative() {
+        format!("{}-{}i", re, -im)
+    } else {
+        format!("{}+{}i", re, im)
+    }
+}
+
+fn mul((a, b): Complex, (c, d): Complex) -> Complex {
+    (a * c - b * d, a * d + b * c)
+}
+
+fn div((a, b): Complex, (c, d): Complex) -> Complex {
+    let denom = c * c + d * d;
+    ((a * c + b * d) / denom, (b * c - a * d) / denom)
+}
+
+fn exp((a, b): Complex) -> Complex {
+    let scale = a.exp();
+    let (sin, cos) = b.sin_cos();
+    (scale * cos, scale * sin)
+}
+
+/// Principal square root.
+fn sqrt((a, b): Complex) -> Complex {
+    let r = a.hypot(b);
+    let re = ((r + a) / 2.0).sqrt();
+    let im = ((r - a) / 2.0).sqrt();
+    (re, if b.is_sign_negative() { -im } else { im })
+}
+
+/// z^w. Small integer exponents use repeated multiplication so results like
+/// i^2 come out exact; everything else goes through exp(w * ln z).
+fn pow(z: Complex, w: Complex) -> Complex {
+    if w.1 == 0.0 && w.0.fract() == 0.0 && w.0.abs() <= 64.0 {
+        let mut result = (1.0, 0.0);
+        for _ in 0..(w.0.abs() as u32) {
+            result = mul(result, z);
+        }
+        return if w.0 < 0.0 {
+            div((1.0, 0.0), result)
+        } else {
+            result
+        };
+    }
+    if z == (0.0, 0.0) {
+        return (0.0, 0.0);
+    }
+    let ln = (z.0.hypot(z.1).ln(), z.1.atan2(z.0));
+    exp(mul(w, ln))
+}
+
+/// Applies a complex-aware binary operator. Returns None if `token` has no complex form.
+pub fn binary(token: &str, a: Complex, b: Complex) -> Option<Complex> {
+    match token {
+        "+" => Some((a.0 + b.0, a.1 + b.1)),
+        "-" => Some((a.0 - b.0, a.1 - b.1)),
+        "*" => Some(mul(a, b)),
+        "/" => Some(div(a, b)),
+        "**" => Some(pow(a, b)),
+        _ => None,
+    }
+}
+
+/// Applies a complex-aware unary operator. Returns None if `token` has no complex form.
+pub fn unary(token: &str, z: Complex) -> Option<StackItem> {
+    match token {
+        "sqrt" => Some(normalize(sqrt(z))),
+        "exp" => Some(normalize(exp(z))),
+        "abs" => Some(StackItem::Number(z.0.hypot(z.1))),
+        "real" => Some(StackItem::Number(z.0)),
+        "imag" => Some(StackItem::Number(z.1)),
+        "conj" => Some(normalize((z.0, -z.1))),
+        _ => None,
+    }
+}
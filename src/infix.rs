@@ -0,0 +1,78 @@
+use super::{OPERATOR_DATA, OperatorAction};
+
+/// Precedence used for atoms (numbers, constants, function calls): never parenthesized.
+const ATOM: u8 = u8::MAX;
+
+/// Infix binary operators: (token, precedence, right-associative).
+const INFIX_OPERATORS: &[(&str, u8, bool)] = &[
+    ("+", 1, false),
+    ("-", 1, false),
+    ("*", 2, false),
+    ("/", 2, false),
+    ("%", 2, false),
+    ("**", 3, true),
+];
+
+/// Wraps an expression in parentheses when `needs_parens` is set.
+fn wrap((expr, _): (String, u8), needs_parens: bool) -> String {
+    if needs_parens {
+        format!("({})", expr)
+    } else {
+        expr
+    }
+}
+
+/// Converts a sequence of RPN tokens into an infix expression with minimal parentheses.
+///
+/// Arithmetic operators (`+ - * / % **`) are written infix; other unary and binary
+/// operators from the operator table are written as function calls, e.g. `sqrt(x)`
+/// or `log(a, b)`. Stack-manipulating commands have no infix form and are rejected.
+pub fn rpn_to_infix(tokens: &[&str]) -> Result<String, &'static str> {
+    // Each entry is a sub-expression and the precedence of its outermost operator.
+    let mut exprs: Vec<(String, u8)> = Vec::new();
+
+    for token in tokens {
+        if let Some((_, prec, right_assoc)) = INFIX_OPERATORS.iter().find(|op| op.0 == *token) {
+            let (b, a) = match (exprs.pop(), exprs.pop()) {
+                (Some(b), Some(a)) => (b, a),
+                _ => return Err("rpn_to_infix: not enough operands"),
+            };
+            let left_parens = a.1 < *prec || (*right_assoc && a.1 == *prec);
+            let right_parens = b.1 < *prec || (!*right_assoc && b.1 == *prec);
+            let expr = format!(
+                "{} {} {}",
+                wrap(a, left_parens),
+                token,
+                wrap(b, right_parens)
+            );
+            exprs.push((expr, *prec));
+            continue;
+        }
+
+        match OPERATOR_DATA.get(token) {
+            Some((_, _, OperatorAction::PushConstant(_))) => exprs.push((token.to_string(), ATOM)),
+            Some((_, _, OperatorAction::Unary(_))) => {
+                let (a, _) = exprs.pop().ok_or("rpn_to_infix: not enough operands")?;
+                exprs.push((format!("{}({})", token, a), ATOM));
+            }
+            Some((_, _, OperatorAction::Binary(_))) => {
+                let (b, a) = match (exprs.pop(), exprs.pop()) {
+                    (Some((b, _)), Some((a, _))) => (b, a),
+                    _ => return Err("rpn_to_infix: not enough operands"),
+                };
+                exprs.push((format!("{}({}, {})", token, a, b), ATOM));
+            }
+            Some((_, _, OperatorAction::Special(_))) => {
+                return Err("rpn_to_infix: operator has no infix form");
+            }
+            None if token.parse::<f64>().is_ok() => exprs.push((token.to_string(), ATOM)),
+            None => return Err("rpn_to_infix: unrecognized token"),
+        }
+    }
+
+    match exprs.pop() {
+        Some((expr, _)) if exprs.is_empty() => Ok(expr),
+        Some(_) => Err("rpn_to_infix: too many operands (expression is incomplete)"),
+        None => Err("rpn_to_infix: empty expression"),
+    }
+}
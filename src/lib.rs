@@ -1,7 +1,11 @@
 use phf::{Map, phf_map};
 use std::collections::HashMap;
+use std::env;
 use std::f64::consts;
+use std::fs::{self, OpenOptions};
 use std::io::{self, Write};
+use std::num::IntErrorKind;
+use std::path::Path;
 use thousands::Separable;
 
 /// Type alias for a function that operates on one f64 and returns an f64.
@@ -9,15 +13,98 @@ type UnaryHandler = fn(f64) -> f64;
 /// Type alias for a function that operates on two f64s and returns an f64.
 type BinaryHandler = fn(f64, f64) -> f64;
 mod binary;
+mod complex;
+mod infix;
+mod rng;
+mod sigfigs;
 mod special;
+mod stats;
 mod unary;
 
+pub use infix::rpn_to_infix;
+pub use sigfigs::{count_sig_figs, displayed_sig_figs, propagate_sig_figs};
+pub use special::{
+    day_of_week, digit_sum, digital_root, format_base, format_percent, help_details, help_example,
+    help_text, join_numbers, luhn_valid, number_to_words, render_histogram, round_sig,
+    session_summary, to_roman,
+};
+pub use stats::{geometric_mean, harmonic_mean, least_squares, statistical_mode};
+
+/// How floats are converted to integers for base display and other integer operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CastMode {
+    /// Toward zero, like `as i64`.
+    #[default]
+    Trunc,
+    /// To the nearest integer, halfway cases away from zero.
+    Round,
+    /// Toward negative infinity.
+    Floor,
+    /// Toward positive infinity.
+    Ceil,
+}
+
+/// Converts x to an integer using the given mode.
+/// Returns None for NaN and values outside the i64 range.
+pub fn to_integer(x: f64, mode: CastMode) -> Option<i64> {
+    let rounded = match mode {
+        CastMode::Trunc => x.trunc(),
+        CastMode::Round => x.round(),
+        CastMode::Floor => x.floor(),
+        CastMode::Ceil => x.ceil(),
+    };
+    // i64::MAX is not representable as f64; 2^63 is the first value out of range.
+    if rounded.is_nan() || rounded < i64::MIN as f64 || rounded >= 9_223_372_036_854_775_808.0 {
+        return None;
+    }
+    Some(rounded as i64)
+}
+
+/// Unit used for angles by the trig operators, cis and normangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AngleMode {
+    #[default]
+    Radians,
+    Degrees,
+}
+
+impl AngleMode {
+    /// One full turn in this unit.
+    pub fn full_turn(self) -> f64 {
+        match self {
+            AngleMode::Radians => consts::TAU,
+            AngleMode::Degrees => 360.0,
+        }
+    }
+}
+
+/// What happens to the last answer when a line leaves the stack empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnswerMode {
+    /// The previous answer stays available via `a`.
+    #[default]
+    Persist,
+    /// The answer is cleared, so `a` errors until a line ends with a number again.
+    /// `softclear` still keeps the number it clears.
+    ClearOnEmpty,
+}
+
 /// Represents an item that can be placed on the RPN stack.
-/// It can be a floating-point number or a string key for storage.
-#[derive(Debug, Clone)]
+/// It can be a floating-point number, a complex number, or a string key for storage.
+/// Equality is exact: numbers compare with `==` (so NaN never equals itself).
+#[derive(Debug, Clone, PartialEq)]
 pub enum StackItem {
     Number(f64),
     Key(String),
+    /// A complex number as (real, imaginary) parts.
+    Complex(f64, f64),
+}
+
+/// Pushes operands back onto the stack after a failed operation.
+/// `items` must be given in their original stack order (deepest first), so the
+/// stack ends up exactly as it was before the operation popped them.
+pub(crate) fn restore_operands(stack: &mut Vec<StackItem>, items: &[StackItem]) {
+    stack.extend_from_slice(items);
 }
 
 // --- Centralized Operator Data Structures ---
@@ -43,15 +130,40 @@ const OPERATOR_DATA: Map<&'static str, (&'static str, &'static str, OperatorActi
     "-" => ("Binary", "a b - | Subtraction (a - b)", OperatorAction::Binary(|a, b| a - b)),
     "*" => ("Binary", "a b * | Multiplication (a * b)", OperatorAction::Binary(|a, b| a * b)),
     "/" => ("Binary", "a b / | Division (a / b)", OperatorAction::Binary(|a, b| a / b)),
+    "//" => ("Binary", "a b // | Floor division, rounds toward -inf (x 0 // gives +/-inf)", OperatorAction::Binary(|a, b| (a / b).floor())),
     "**" => ("Binary", "a b ** | Power (a^b)", OperatorAction::Binary(binary::power_op)),
     "%" => ("Binary", "a b % | Euclidean Remainder (a mod b)", OperatorAction::Binary(f64::rem_euclid)),
     "%%" => ("Binary", "a b %% | Percent Change ((b - a) / a * 100)", OperatorAction::Binary(binary::percent_change)),
+    "absdiff" => ("Binary", "a b absdiff | Absolute difference |a - b| (order does not matter)", OperatorAction::Binary(|a, b| (a - b).abs())),
     "log" => ("Binary", "a b log | Logarithm (log_b(a))", OperatorAction::Binary(binary::log_op)),
+    "logb" => ("Binary", "a b logb | Log base b of a (8 2 logb = 3); errors unless a > 0, b > 0 and b != 1", OperatorAction::Special("logb")),
+    "rsub" => ("Binary", "a b rsub | Reverse subtraction (b - a)", OperatorAction::Binary(binary::reverse_sub)),
+    "rdiv" => ("Binary", "a b rdiv | Reverse division (b / a)", OperatorAction::Binary(binary::reverse_div)),
+    "rpow" => ("Binary", "a b rpow | Reverse power (b^a)", OperatorAction::Binary(binary::reverse_pow)),
+    "rootn" => ("Binary", "x n rootn | Real nth root of x (exact for integer roots, negative x needs odd n)", OperatorAction::Special("rootn")),
     "atan2" => ("Binary", "y x atan2 | Arc tangent of y/x (result in radians)", OperatorAction::Binary(binary::atan2_op)),
 
+    // Comparison
+    "cmp" => ("Comparison", "a b cmp | Three-way comparison: -1 if a < b, 0 if equal, 1 if a > b (total order: -0 < 0, NaN above +inf)", OperatorAction::Binary(binary::compare)),
+    "between" => ("Comparison", "value lo hi between | 1 if lo <= value <= hi (inclusive), else 0", OperatorAction::Special("between")),
+
     // Constants
     "pi" => ("Constant", "pi | Push the value of pi", OperatorAction::PushConstant(consts::PI)),
     "e" => ("Constant", "e | Push the value of Euler's number (e)", OperatorAction::PushConstant(consts::E)),
+    "2pi" => ("Constant", "2pi | Push 2 * pi (tau)", OperatorAction::PushConstant(consts::TAU)),
+    "pi/2" => ("Constant", "pi/2 | Push pi / 2", OperatorAction::PushConstant(consts::FRAC_PI_2)),
+    "pi/3" => ("Constant", "pi/3 | Push pi / 3", OperatorAction::PushConstant(consts::FRAC_PI_3)),
+    "pi/4" => ("Constant", "pi/4 | Push pi / 4", OperatorAction::PushConstant(consts::FRAC_PI_4)),
+    "pi/6" => ("Constant", "pi/6 | Push pi / 6", OperatorAction::PushConstant(consts::FRAC_PI_6)),
+
+    // Physical constants (SI units, CODATA 2018 values)
+    "c0" => ("Physics", "c0 | Speed of light in vacuum (m/s)", OperatorAction::PushConstant(299_792_458.0)),
+    "grav" => ("Physics", "grav | Newtonian constant of gravitation (m^3 kg^-1 s^-2)", OperatorAction::PushConstant(6.674_30e-11)),
+    "planck" => ("Physics", "planck | Planck constant (J s)", OperatorAction::PushConstant(6.626_070_15e-34)),
+    "avogadro" => ("Physics", "avogadro | Avogadro constant (1/mol)", OperatorAction::PushConstant(6.022_140_76e23)),
+    "boltzmann" => ("Physics", "boltzmann | Boltzmann constant (J/K)", OperatorAction::PushConstant(1.380_649e-23)),
+    "units?" => ("Physics", "units? | Show the unit of the physical constant just pushed", OperatorAction::Special("units")),
+    "constants" => ("Physics", "constants | List the physical constants with their values and units", OperatorAction::Special("constants")),
 
     // Unary/Trig/Rounding (Unary Handler)
     "sqrt" => ("Unary", "a sqrt | Square root", OperatorAction::Unary(f64::sqrt)),
@@ -62,26 +174,205 @@ const OPERATOR_DATA: Map<&'static str, (&'static str, &'static str, OperatorActi
     "asin" => ("Unary", "a asin | Arc sine (result in radians)", OperatorAction::Unary(f64::asin)),
     "atan" => ("Unary", "a atan | Arc tangent (result in radians)", OperatorAction::Unary(f64::atan)),
     "exp" => ("Unary", "a exp | e raised to the power of a (e^a)", OperatorAction::Unary(f64::exp)),
+    "exp2" => ("Unary", "a exp2 | 2 raised to the power of a (2^a), the inverse of a base-2 log", OperatorAction::Unary(f64::exp2)),
+    "ln1p" => ("Unary", "a ln1p | ln(1 + a), accurate for tiny a (prefer over 1 + ln)", OperatorAction::Unary(f64::ln_1p)),
+    "expm1" => ("Unary", "a expm1 | e^a - 1, accurate for tiny a (prefer over exp 1 -)", OperatorAction::Unary(f64::exp_m1)),
+    "abs" => ("Unary", "a abs | Absolute value (modulus for complex a)", OperatorAction::Unary(f64::abs)),
     "ceil" => ("Rounding", "a ceil | Ceiling (rounds up)", OperatorAction::Unary(f64::ceil)),
     "floor" => ("Rounding", "a floor | Floor (rounds down)", OperatorAction::Unary(f64::floor)),
+    "rounde" => ("Rounding", "a rounde | Round to nearest integer, ties to even (2.5 -> 2, 3.5 -> 4)", OperatorAction::Unary(unary::round_half_even)),
+    "sigfig" => ("Rounding", "a n sigfig | Round a to n significant figures (1-15)", OperatorAction::Special("sigfig")),
     "deg" => ("Conversions", "a deg | Convert angle from radians to degrees", OperatorAction::Unary(unary::rad_to_deg)),
     "rad" => ("Conversions", "a rad | Convert angle from degrees to radians", OperatorAction::Unary(unary::deg_to_rad)),
+    "?deg" => ("Conversions", "a ?deg | Show a (radians) in degrees without changing the stack", OperatorAction::Special("peek_angle")),
+    "?rad" => ("Conversions", "a ?rad | Show a (degrees) in radians without changing the stack", OperatorAction::Special("peek_angle")),
+    "anglemode" => ("Conversions", "\"deg\" anglemode | Angle unit for trig, cis and normangle: \"deg\" or \"rad\" (default)", OperatorAction::Special("angle_mode")),
+    "normangle" => ("Conversions", "a normangle | Reduce an angle to [0, 360) in degree mode or [0, 2pi) in radian mode", OperatorAction::Special("normalize_angle")),
 
     // Special/Custom Logic (Handled explicitly in process_token's Special match)
     "!" => ("Combinatorics", "n ! | Factorial (n!)", OperatorAction::Special("factorial")),
     "P" => ("Combinatorics", "n k P | Permutations P(n, k)", OperatorAction::Special("permutations")),
     "C" => ("Combinatorics", "n k C | Combinations C(n, k)", OperatorAction::Special("combinations")),
-    "<>" => ("Stack", "a b <> | Swap the top two items", OperatorAction::Special("swap")),
+    "factratio" => ("Combinatorics", "n m factratio | n!/m! as (m+1)*...*n, for n >= m (100 98 factratio = 9900)", OperatorAction::Special("factratio")),
+    "simplify" => ("NumberTheory", "n d simplify | Reduce the fraction n/d by gcd(n, d), pushing n' d'", OperatorAction::Special("simplify")),
+    "digitsum" => ("NumberTheory", "n digitsum | Sum of the decimal digits of the integer part of abs(n) (12345 digitsum = 15)", OperatorAction::Special("digitsum")),
+    "digitalroot" => ("NumberTheory", "n digitalroot | Repeat digitsum until one digit remains (12345 digitalroot = 6)", OperatorAction::Special("digitalroot")),
+    "mkcomplex" => ("Complex", "re im mkcomplex | Build the complex number re+im*i", OperatorAction::Special("make_complex")),
+    "i" => ("Complex", "a i | Turn a into the imaginary number a*i", OperatorAction::Special("imaginary")),
+    "real" => ("Complex", "a real | Real part of a", OperatorAction::Unary(|a| a)),
+    "imag" => ("Complex", "a imag | Imaginary part of a (0 for real numbers)", OperatorAction::Unary(|_| 0.0)),
+    "conj" => ("Complex", "a conj | Complex conjugate of a", OperatorAction::Unary(|a| a)),
+    "cis" => ("Complex", "a cis | Push cos(a) then sin(a) (a in radians), i.e. re and im of e^(ia)", OperatorAction::Special("cis")),
+    "dow" => ("Calendar", "year month day dow | Day of week (0 = Sunday ... 6 = Saturday)", OperatorAction::Special("day_of_week")),
+    "geomean" => ("Statistics", "x1 .. xn geomean | Geometric mean of the numbers on top of the stack", OperatorAction::Special("geomean")),
+    "harmean" => ("Statistics", "x1 .. xn harmean | Harmonic mean of the numbers on top of the stack", OperatorAction::Special("harmean")),
+    "minall" => ("Statistics", "x1 .. xn minall | Smallest of the numbers on top of the stack", OperatorAction::Special("min_all")),
+    "maxall" => ("Statistics", "x1 .. xn maxall | Largest of the numbers on top of the stack", OperatorAction::Special("max_all")),
+    "sumsq" => ("Statistics", "x1 .. xn sumsq | Sum of the squares of the numbers on top of the stack", OperatorAction::Special("sumsq")),
+    "rms" => ("Statistics", "x1 .. xn rms | Root mean square of the numbers on top of the stack", OperatorAction::Special("rms")),
+    "diff" => ("Statistics", "x1 .. xn diff | Replace the numbers on top with their successive differences (x2-x1, ..)", OperatorAction::Special("diff")),
+    "cumsum" => ("Statistics", "x1 .. xn cumsum | Replace the numbers on top with their running sums", OperatorAction::Special("cumsum")),
+    "pcterr" => ("Statistics", "actual expected pcterr | Percent error: abs(actual - expected) / abs(expected) * 100", OperatorAction::Special("pcterr")),
+    "minmaxnorm" => ("Statistics", "x1 .. xn minmaxnorm | Rescale the top run to [0, 1]: (x - min) / (max - min), all 0 if every value is equal", OperatorAction::Special("minmaxnorm")),
+    "countgt" => ("Statistics", "x1 .. xn t countgt | Push how many numbers of the run below t are greater than t (t is consumed, the run stays)", OperatorAction::Special("countgt")),
+    "countlt" => ("Statistics", "x1 .. xn t countlt | Push how many numbers of the run below t are less than t (t is consumed, the run stays)", OperatorAction::Special("countlt")),
+    "counteq" => ("Statistics", "x1 .. xn t counteq | Push how many numbers of the run below t equal t (t is consumed, the run stays)", OperatorAction::Special("counteq")),
+    "mode" => ("Statistics", "x1 .. xn mode | Most frequent number in the top run (exact matches; ties go to the smallest)", OperatorAction::Special("mode")),
+    "zscore" => ("Statistics", "x1 .. xn zscore | Standardize the top run: (x - mean) / s, with s the sample standard deviation", OperatorAction::Special("zscore")),
+    "linfit" => ("Statistics", "x1 y1 .. xn yn linfit | Least-squares line through the (x, y) pairs on top: pushes slope, intercept", OperatorAction::Special("linfit")),
+    "range" => ("Sequences", "start stop step range | Push start, start+step, ... up to and including stop", OperatorAction::Special("range")),
+    "map" => ("Functional", "x1 .. xn \"name\" map | Apply a unary operator or macro to each number of the top run", OperatorAction::Special("map")),
+    "zipwith" => ("Functional", "a1 .. an b1 .. bn n \"op\" zipwith | Apply a binary operator pairwise: (a1 op b1) .. (an op bn)", OperatorAction::Special("zipwith")),
+    "det2" => ("LinearAlgebra", "a b c d det2 | Determinant of the 2x2 matrix [[a, b], [c, d]] (row by row): ad - bc", OperatorAction::Special("det2")),
+    "<>" => ("Stack", "a b <> | Swap the top two items (numbers or keys)", OperatorAction::Special("swap")),
+    "swapkeep" => ("Stack", "a b swapkeep | Swap the top two items only if both are numbers", OperatorAction::Special("swap_numbers_only")),
+    "rot" => ("Stack", "a b c rot | Rotate the top three items: the third comes to the top (a b c -> b c a)", OperatorAction::Special("rot")),
+    "rot-" => ("Stack", "a b c rot- | Rotate the top three items the other way: the top goes third (a b c -> c a b)", OperatorAction::Special("rot_back")),
+    "sink" => ("Stack", "x sink | Move the top item to the bottom of the stack (1 2 3 -> 3 1 2)", OperatorAction::Special("sink")),
+    "float" => ("Stack", "float | Move the bottom item to the top of the stack (1 2 3 -> 2 3 1)", OperatorAction::Special("float")),
+    "bury" => ("Stack", "x n bury | Move the top item down to level n (1 = top), shifting the others up", OperatorAction::Special("bury")),
+    "swapblock" => ("Stack", "x1 .. xn y1 .. yn n swapblock | Swap the top n items with the n items below them", OperatorAction::Special("swapblock")),
+    "keep" => ("Stack", "n keep | Discard everything except the top n items", OperatorAction::Special("keep")),
+    "dedup" => ("Stack", "x1 .. xn dedup | Remove repeated numbers from the top run, keeping first occurrences", OperatorAction::Special("dedup")),
+    "sort" => ("Stack", "x1 .. xn sort | Sort the top run of numbers ascending: -inf first, then -0 before 0, NaN last", OperatorAction::Special("sort")),
+    "peekn" => ("Stack", "n peekn | Show the item n levels down (0 = top) without changing the stack", OperatorAction::Special("peek_at")),
+    "undo" => ("Stack", "undo | Restore the stack as it was before the last operation", OperatorAction::Special("undo")),
+    "redo" => ("Stack", "redo | Reapply the last undone operation (cleared by any new operation)", OperatorAction::Special("redo")),
     "c" => ("Stack", "c | Clear the stack", OperatorAction::Special("clear")),
+    "softclear" => ("Stack", "softclear | Clear the stack, keeping the top number as the last answer (a)", OperatorAction::Special("soft_clear")),
+    "capture" => ("Stack", "x capture | Make x the last answer, leaving it on the stack; it is kept across lines until a recalls it", OperatorAction::Special("capture")),
     "a" => ("Stack", "a | Recall last successful answer", OperatorAction::Special("answer")),
+    "ansmode" => ("Stack", "\"mode\" ansmode | When a line leaves the stack empty: persist (default) keeps the last answer, clear forgets it", OperatorAction::Special("answer_mode")),
+    "-a" => ("Stack", "-a | Recall the last answer with its sign flipped (shorthand for a -1 *)", OperatorAction::Special("answer")),
+    "x<>a" => ("Stack", "x x<>a | Exchange the top number with the last answer (both change); the answer is kept until a recalls it", OperatorAction::Special("swap_answer")),
     "sto" => ("Memory", "value \"key\" sto | Store value to key", OperatorAction::Special("store")),
     "rcl" => ("Memory", "\"key\" rcl | Recall value from key", OperatorAction::Special("recall")),
-    "hex" => ("Display", "a hex | Display a in hexadecimal (i64 cast)", OperatorAction::Special("display_base")),
-    "bin" => ("Display", "a bin | Display a in binary (i64 cast)", OperatorAction::Special("display_base")),
-    "oct" => ("Display", "a oct | Display a in octal (i64 cast)", OperatorAction::Special("display_base")),
+    "sto<>" => ("Memory", "value \"key\" sto<> | Store value to an existing key and push its old value", OperatorAction::Special("store_swap")),
+    "sto?" => ("Memory", "value cond \"key\" sto? | Store value to key if cond is nonzero (all three are consumed either way)", OperatorAction::Special("store_if")),
+    "savestack" => ("Memory", "\"name\" savestack | Save a copy of the (all-number) stack under name", OperatorAction::Special("save_stack")),
+    "loadstack" => ("Memory", "\"name\" loadstack | Push the stack saved under name", OperatorAction::Special("load_stack")),
+    "pack" => ("Memory", "x1 .. xn n \"name\" pack | Move the top n numbers into a list saved under name", OperatorAction::Special("pack")),
+    "unpack" => ("Memory", "\"name\" unpack | Push the list packed under name", OperatorAction::Special("unpack")),
+    "hex" => ("Display", "a hex | Display a in hexadecimal (integer per castmode)", OperatorAction::Special("display_base")),
+    "bin" => ("Display", "a bin | Display a in binary (integer per castmode)", OperatorAction::Special("display_base")),
+    "?pct" => ("Display", "a ?pct | Show a as a percentage (0.1523 -> 15.23%) without changing the stack", OperatorAction::Special("percent")),
+    "join" => ("Display", "x1 .. xn \"sep\" join | Print the top run of numbers joined by sep (\\t = tab, \\n = newline), keeping them on the stack", OperatorAction::Special("join")),
+    "words" => ("Display", "a words | Spell out a (rounded to an integer) in English words", OperatorAction::Special("words")),
+    "roman" => ("Display", "a roman | Display a (rounded, 1-3999) as a Roman numeral", OperatorAction::Special("roman")),
+    "inbase" => ("Display", "b inbase | Read bare integers in base b (2-36, at most 2^53); \"10\" inbase returns to decimal. Above base 13, 3d6 is a number, not dice", OperatorAction::Special("input_base")),
+    "hist" => ("Display", "x1 .. xn hist | Draw the numbers on top of the stack as a bar chart", OperatorAction::Special("histogram")),
+    "castmode" => ("Display", "\"mode\" castmode | Integer conversion for hex/bin/oct: trunc (default), round, floor, ceil", OperatorAction::Special("cast_mode")),
+    "oct" => ("Display", "a oct | Display a in octal (integer per castmode)", OperatorAction::Special("display_base")),
+    "fromdigits" => ("Display", "\"digits\" b fromdigits | Read the digit string in base b (2-36) as a number", OperatorAction::Special("from_digits")),
+    "type" => ("Meta", "a type | Describe the type and value of the top item", OperatorAction::Special("describe")),
+    "def" => ("Programming", "\"body\" \"name\" def | Define a macro that runs the tokens in body", OperatorAction::Special("define")),
+    "oplimit" => ("Programming", "n oplimit | Abort a line after n operator executions (default 10,000,000)", OperatorAction::Special("op_limit")),
+    "dowhile" => ("Programming", "\"body\" \"cond\" dowhile | Run macro body, then macro cond on a scratch copy of the stack; repeat while cond leaves a nonzero top", OperatorAction::Special("do_while")),
+    "total" => ("Meta", "total | Toggle running total mode (adds up every number you enter)", OperatorAction::Special("toggle_total")),
+    "luhn" => ("Validation", "n luhn | 1 if the digits of n pass the Luhn checksum, else 0", OperatorAction::Special("luhn")),
+    "run" => ("IO", "\"file\" run | Run each line of a script file as if typed, stopping at the first failing line", OperatorAction::Special("run")),
+    "export" => ("IO", "\"file\" export | Write the stack to file as one comma-separated line (CSV)", OperatorAction::Special("export")),
+    "autolog" => ("IO", "\"file\" autolog | Append every line result to file; \"off\" autolog stops", OperatorAction::Special("autolog")),
+    "alias" => ("Meta", "\"name\" \"target\" alias | Make name a shortcut for an operator or macro", OperatorAction::Special("alias")),
+    "profile" => ("Meta", "profile | Print how often each operator has been used", OperatorAction::Special("profile")),
+    "lock" => ("Meta", "lock | Make the stack read-only: only display and help commands work until unlock", OperatorAction::Special("lock")),
+    "unlock" => ("Meta", "unlock | Make the stack writable again", OperatorAction::Special("unlock")),
+    "trace" => ("Meta", "trace | Toggle printing each token run inside a macro, with the resulting stack", OperatorAction::Special("toggle_trace")),
+    "chain" => ("Meta", "chain | Toggle chain mode: a line entered on an empty stack starts by pushing the last answer (no need for a)", OperatorAction::Special("toggle_chain")),
+    "sigtrack" => ("Meta", "sigtrack | Toggle warnings when a + - * / result shows more significant figures than its inputs", OperatorAction::Special("toggle_sigtrack")),
+    "step" => ("Meta", "step | Toggle learning mode: after each command, show what it popped and pushed", OperatorAction::Special("toggle_step")),
+    "checkpoint" => ("Meta", "\"name\" checkpoint | Save the stack, storage and last answer under name", OperatorAction::Special("checkpoint")),
+    "restore" => ("Meta", "\"name\" restore | Bring back the stack, storage and last answer saved under name", OperatorAction::Special("restore")),
+    "explain" => ("Meta", "explain | Show how the top item was computed, e.g. 5 3 + = 8 (history does not survive sto/rcl)", OperatorAction::Special("explain")),
+    "reset" => ("Meta", "reset | Start over: clear the stack, storage, macros and answer, and restore every mode to its default", OperatorAction::Special("reset")),
+    "session" => ("Meta", "session | Show the count, sum, mean, min and max of every line result so far", OperatorAction::Special("session")),
+    "hints" => ("Meta", "hints | Toggle hints explaining stack underflow errors", OperatorAction::Special("toggle_hints")),
+    "total?" => ("Meta", "total? | Print the running total", OperatorAction::Special("show_total")),
+    "totalc" => ("Meta", "totalc | Reset the running total to zero", OperatorAction::Special("clear_total")),
+    "assert" => ("Meta", "expected actual assert | Fail unless actual equals expected (within 1e-9)", OperatorAction::Special("assert")),
+    "prompt" => ("Meta", "\"template\" prompt | Set the prompt; placeholders {stack} {depth} {ans}, \\n for a newline, \"default\" to reset", OperatorAction::Special("set_prompt")),
+    "seed" => ("Meta", "n seed | Seed the random generator used for dice rolls (e.g., 3d6); n is an integer from 0 to 2^53", OperatorAction::Special("seed")),
     "help" => ("Meta", "\"func_name\" help | List all functions or show usage for [func_name]", OperatorAction::Special("help")),
 };
 
+/// How many stack items an operator consumes, as reported by `operators`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    /// Special commands, whose operands depend on the stack or on their arguments.
+    Variable,
+}
+
+/// One entry of the operator table, as reported by `operators`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub token: &'static str,
+    pub group: &'static str,
+    pub usage: &'static str,
+    pub arity: Arity,
+}
+
+/// Every built-in operator with its help group, usage and arity, sorted by
+/// group and then by token.
+pub fn operators() -> Vec<OperatorInfo> {
+    let mut infos: Vec<OperatorInfo> = OPERATOR_DATA
+        .entries()
+        .map(|(token, (group, usage, action))| OperatorInfo {
+            token,
+            group,
+            usage,
+            arity: match action {
+                OperatorAction::PushConstant(_) => Arity::Fixed(0),
+                OperatorAction::Unary(_) => Arity::Fixed(1),
+                OperatorAction::Binary(_) => Arity::Fixed(2),
+                OperatorAction::Special(_) => Arity::Variable,
+            },
+        })
+        .collect();
+    infos.sort_by_key(|info| (info.group, info.token));
+    infos
+}
+
+/// Commands that only read the stack, and so still work while it is locked.
+pub(crate) const READ_ONLY_TOKENS: &[&str] = &[
+    "hex",
+    "bin",
+    "oct",
+    "words",
+    "roman",
+    "hist",
+    "?deg",
+    "?rad",
+    "?pct",
+    "type",
+    "help",
+    "total?",
+    "profile",
+    "session",
+    "explain",
+    "constants",
+    "units?",
+    "lock",
+    "unlock",
+];
+
+/// Units of the physical constants in `OPERATOR_DATA`, in display order.
+pub(crate) const PHYSICAL_UNITS: &[(&str, &str)] = &[
+    ("c0", "m/s"),
+    ("grav", "m^3 kg^-1 s^-2"),
+    ("planck", "J s"),
+    ("avogadro", "1/mol"),
+    ("boltzmann", "J/K"),
+];
+
+/// The SI unit of a physical constant token, e.g. "m/s" for "c0".
+pub fn unit_of(constant: &str) -> Option<&'static str> {
+    PHYSICAL_UNITS
+        .iter()
+        .find(|(name, _)| *name == constant)
+        .map(|(_, unit)| *unit)
+}
+
 /// Helper to convert various Unicode digits and separators to ASCII digits and standard separators.
 fn unicode_to_ascii(c: char) -> char {
     match c {
@@ -117,76 +408,910 @@ fn unicode_to_ascii(c: char) -> char {
     }
 }
 
-/// The core function to process a single input token.
-pub fn process_token(
-    stack: &mut Vec<StackItem>,
-    token: &str,
-    last_answer: &mut Option<f64>,
-    storage: &mut HashMap<String, f64>,
-) -> Result<(), &'static str> {
-    // 1. Check for Quoted String Key
-    if token.starts_with('"') && token.ends_with('"') && token.len() > 1 {
-        let key = token.trim_matches('"').to_string();
-        stack.push(StackItem::Key(key));
-        return Ok(());
+/// Returns the sorted operator names and stored keys that start with `prefix`.
+/// This is the lookup behind tab-completion style previews.
+pub fn matches_for_prefix(prefix: &str, storage: &HashMap<String, f64>) -> Vec<String> {
+    let mut matches: Vec<String> = OPERATOR_DATA
+        .keys()
+        .map(|op| op.to_string())
+        .chain(storage.keys().cloned())
+        .filter(|name| name.starts_with(prefix))
+        .collect();
+    matches.sort();
+    matches.dedup();
+    matches
+}
+
+/// The default REPL prompt: the stack on one line, then the input marker.
+pub const DEFAULT_PROMPT: &str = "Stack: {stack}\n> ";
+
+/// Formats one stack item for display, e.g. `1,234.5`, `"key"` or `1+2i`.
+pub fn format_item(item: &StackItem) -> String {
+    match item {
+        StackItem::Number(val) => val.separate_with_commas(),
+        // Display keys surrounded by their quotes
+        StackItem::Key(key) => format!("\"{}\"", key),
+        StackItem::Complex(re, im) => complex::format((*re, *im)),
+    }
+}
+
+/// Explains what an operation did to the stack for step mode, e.g.
+/// `popped 3 and 4, pushed 7`.
+pub fn describe_effect(popped: &[StackItem], pushed: &[StackItem]) -> String {
+    let list = |items: &[StackItem]| {
+        let names: Vec<String> = items.iter().map(format_item).collect();
+        match names.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => String::new(),
+        }
+    };
+    match (popped.is_empty(), pushed.is_empty()) {
+        (true, true) => "stack unchanged".to_string(),
+        (false, true) => format!("popped {}", list(popped)),
+        (true, false) => format!("pushed {}", list(pushed)),
+        (false, false) => format!("popped {}, pushed {}", list(popped), list(pushed)),
+    }
+}
+
+/// True if the stack holds exactly the `expected` numbers, deepest first.
+/// Any key or complex item makes the comparison fail.
+pub fn stack_equals(stack: &[StackItem], expected: &[f64]) -> bool {
+    stack.len() == expected.len()
+        && stack
+            .iter()
+            .zip(expected)
+            .all(|(item, val)| matches!(item, StackItem::Number(n) if n == val))
+}
+
+/// Formats the stack for display, e.g. `[1,234.5, "key", 1+2i]`.
+pub fn format_stack(stack: &[StackItem]) -> String {
+    // Manually format the stack for a cleaner look.
+    let display_content: Vec<String> = stack.iter().map(format_item).collect();
+
+    // Join the items and wrap in square brackets
+    format!("[{}]", display_content.join(", "))
+}
+
+/// Renders a prompt template. Supported placeholders are `{stack}` (the formatted
+/// stack), `{depth}` (number of items) and `{ans}` (the last answer, or `none`).
+/// `{{` and `}}` produce literal braces; unknown placeholders are left as-is.
+pub fn render_prompt(template: &str, stack: &[StackItem], last_answer: Option<f64>) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+
+        let (replacement, len) = if rest.starts_with("{{") {
+            ("{".to_string(), 2)
+        } else if rest.starts_with("}}") {
+            ("}".to_string(), 2)
+        } else if rest.starts_with("{stack}") {
+            (format_stack(stack), 7)
+        } else if rest.starts_with("{depth}") {
+            (stack.len().to_string(), 7)
+        } else if rest.starts_with("{ans}") {
+            let ans = last_answer.map_or("none".to_string(), |a| a.separate_with_commas());
+            (ans, 5)
+        } else {
+            (rest[..1].to_string(), 1)
+        };
+        out.push_str(&replacement);
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns a beginner-friendly hint if `token` is a unary or binary operator
+/// and a stack of `depth` items is too shallow for it. None otherwise.
+pub fn underflow_hint(token: &str, depth: usize) -> Option<String> {
+    let needed = match OPERATOR_DATA.get(token) {
+        Some((_, _, OperatorAction::Unary(_))) => 1,
+        Some((_, _, OperatorAction::Binary(_))) => 2,
+        _ => return None,
+    };
+    if depth >= needed {
+        return None;
     }
+    Some(format!(
+        "'{}' needs {} number{} but the stack has {} - try entering more values first",
+        token,
+        needed,
+        if needed == 1 { "" } else { "s" },
+        depth
+    ))
+}
+
+/// Default cap on operator executions per input line.
+pub const DEFAULT_OP_LIMIT: u64 = 10_000_000;
+
+/// Maximum nesting depth for macros calling macros.
+const MAX_MACRO_DEPTH: usize = 64;
 
-    // 2. Handle Numeric Input
-    let cleaned_token: String = token
+/// How many stack snapshots `undo` (and `redo`) keep.
+const MAX_UNDO_LEVELS: usize = 100;
+
+/// Splits an input line on whitespace, keeping quoted keys (which may
+/// contain spaces, e.g. a macro body like `"1 +"`) together as one token.
+pub fn tokenize(line: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut in_quotes = false;
+
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+        if c.is_whitespace() && !in_quotes {
+            if let Some(s) = start.take() {
+                tokens.push(&line[s..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&line[s..]);
+    }
+    tokens
+}
+
+/// What a token would do if run, as reported by `classify_tokens`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    Number(f64),
+    Key(String),
+    /// A built-in operator, by its name in the operator table.
+    Operator(&'static str),
+    /// Dice notation as (count, sides), e.g. `3d6`.
+    Dice(u64, u64),
+    /// Anything else, including macro and alias names (those live in a `Calculator`).
+    Unknown,
+}
+
+/// Splits and classifies an input line without running it: the comment is
+/// stripped, quoted keys stay whole and numbers are read the same way
+/// `process_token` reads them in decimal input mode.
+pub fn classify_tokens(line: &str) -> Vec<(String, TokenKind)> {
+    let input = line.split('#').next().unwrap_or("").trim();
+    tokenize(input)
+        .into_iter()
+        .map(|token| {
+            let kind = if let Some(key) = quoted_key(token) {
+                TokenKind::Key(key.to_string())
+            } else if let Some(num) = parse_number(token) {
+                TokenKind::Number(num)
+            } else if let Some((count, sides)) = parse_dice(token) {
+                TokenKind::Dice(count, sides)
+            } else if let Some((name, _)) = OPERATOR_DATA.get_entry(token) {
+                TokenKind::Operator(name)
+            } else {
+                TokenKind::Unknown
+            };
+            (token.to_string(), kind)
+        })
+        .collect()
+}
+
+/// The key inside a quoted token such as `"rate"`, or None if it is not quoted.
+fn quoted_key(token: &str) -> Option<&str> {
+    (token.starts_with('"') && token.ends_with('"') && token.len() > 1)
+        .then(|| token.trim_matches('"'))
+}
+
+/// Normalizes number input: Persian/Arabic digits become ASCII digits and
+/// thousands separators (commas) are dropped.
+fn clean_number(token: &str) -> String {
+    token
         .chars()
         .map(unicode_to_ascii)
         .filter(|c| *c != ',')
-        .collect();
+        .collect()
+}
+
+/// Reads a decimal number the way the calculator does: Persian/Arabic digits
+/// are accepted, commas are ignored and `h:mm:ss` / `m:ss` durations become
+/// seconds. Returns None if the token is not a number.
+pub fn parse_number(token: &str) -> Option<f64> {
+    clean_number(token)
+        .parse::<f64>()
+        .ok()
+        .or_else(|| parse_duration(token))
+}
+
+/// Longest provenance kept for an item; longer histories keep their newest end.
+const MAX_HISTORY_LEN: usize = 200;
+
+/// Shortens a provenance string to its last `MAX_HISTORY_LEN` characters.
+fn truncate_history(history: String) -> String {
+    match history.char_indices().rev().nth(MAX_HISTORY_LEN - 1) {
+        Some((cut, _)) if cut > 0 => format!("... {}", &history[cut..]),
+        _ => history,
+    }
+}
+
+/// Upper bounds for dice notation (`NdM`).
+const MAX_DICE: u64 = 1000;
+const MAX_SIDES: u64 = 1_000_000;
+
+/// Parses dice notation: `NdM` rolls N dice with M sides, and `dM` is short for `1dM`.
+/// Returns None if the token is not in dice notation at all.
+fn parse_dice(token: &str) -> Option<(u64, u64)> {
+    let (count, sides) = token.split_once('d')?;
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !is_digits(sides) || !(count.is_empty() || is_digits(count)) {
+        return None;
+    }
+    let count = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    Some((count, sides.parse().ok()?))
+}
+
+/// Parses a duration typed as `h:mm:ss` or `m:ss` into total seconds, so
+/// `1:02:03` is 3723 and `02:30` is 150. Minutes and seconds after the first
+/// field must be below 60; only the seconds may have a fractional part.
+/// Returns None for anything else, including a lone number or four fields.
+fn parse_duration(token: &str) -> Option<f64> {
+    let fields: Vec<&str> = token.split(':').collect();
+    if !(2..=3).contains(&fields.len()) {
+        return None;
+    }
+    let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    let (seconds, leading) = fields.split_last()?;
+    let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, "0"));
+    if !leading.iter().all(|f| is_digits(f)) || !is_digits(whole) || !is_digits(fraction) {
+        return None;
+    }
+
+    let mut total = 0.0;
+    for (i, field) in leading.iter().enumerate() {
+        let value: f64 = field.parse().ok()?;
+        if i > 0 && value >= 60.0 {
+            return None;
+        }
+        total = total * 60.0 + value;
+    }
+    let seconds: f64 = seconds.parse().ok()?;
+    (seconds < 60.0).then_some(total * 60.0 + seconds)
+}
+
+/// A saved copy of the working state, made by `checkpoint` and put back by `restore`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalculatorState {
+    pub stack: Vec<StackItem>,
+    pub storage: HashMap<String, f64>,
+    pub last_answer: Option<f64>,
+}
+
+/// Holds all state for a calculator session: the stack, the last answer,
+/// the storage registers, user-defined macros and the random number generator.
+pub struct Calculator {
+    pub stack: Vec<StackItem>,
+    pub last_answer: Option<f64>,
+    pub storage: HashMap<String, f64>,
+    /// Named copies of the numeric stack (savestack / loadstack).
+    pub stack_storage: HashMap<String, Vec<f64>>,
+    /// Named lists of numbers (pack / unpack), separate from saved stacks.
+    pub list_storage: HashMap<String, Vec<f64>>,
+    /// Template for the interactive prompt (see `render_prompt`).
+    pub prompt: String,
+    /// Sum of every number entered while running total mode is on (None = off).
+    pub running_total: Option<f64>,
+    /// The constant that pushed the top value, until something else changes the stack.
+    pub last_constant: Option<&'static str>,
+    /// When set, only `READ_ONLY_TOKENS` may run (lock / unlock).
+    pub locked: bool,
+    /// When set, every token run inside a macro is logged (see `trace_log`).
+    pub trace: bool,
+    /// Macro steps traced during the current line, e.g. `+ -> [6]`.
+    pub trace_log: Vec<String>,
+    /// When set, every command on a line is followed by a note of what it popped and pushed.
+    pub step: bool,
+    /// When set, a line run on an empty stack starts by pushing the last answer (see `process_line`).
+    pub chain: bool,
+    /// When set, stack underflow errors come with a hint (see `underflow_hint`).
+    pub hints: bool,
+    /// When set, significant figures are tracked through + - * / (see `sig_warning`).
+    pub sig_track: bool,
+    /// Warning for a line whose result shows more precision than its inputs justify.
+    pub sig_warning: Option<String>,
+    /// Significant figures of each stack item, while `sig_track` is on (None = unknown).
+    sig_figs: Vec<Option<u32>>,
+    /// How each stack item was computed, as RPN (see `explain`); None = typed in or unknown.
+    provenance: Vec<Option<String>>,
+    /// The hint for the last failed line, if hints are on and one applies.
+    pub last_hint: Option<String>,
+    /// A fuller message for the last failed line, shown instead of the error
+    /// when set (e.g. the values an `assert` compared).
+    pub error_detail: Option<String>,
+    /// How many times each operator has run successfully (see `profile`).
+    pub usage_counts: HashMap<&'static str, u64>,
+    /// File that every successful line result is appended to (None = autolog off).
+    pub autolog: Option<String>,
+    /// Radix (2-36) for entering bare integers; 10 is normal decimal input (see `inbase`).
+    pub input_base: u32,
+    /// Float-to-integer conversion used by base display (see `to_integer`).
+    pub cast_mode: CastMode,
+    /// Angle unit for trig input/output (see `AngleMode`).
+    pub angle_mode: AngleMode,
+    /// Whether a line ending with an empty stack clears the last answer (see `AnswerMode`).
+    pub answer_mode: AnswerMode,
+    /// Set by `softclear` so the answer it keeps survives `AnswerMode::ClearOnEmpty`.
+    answer_kept: bool,
+    /// Set when a command writes the last answer directly (capture, x<>a): the value is
+    /// held across lines, untouched by the end-of-line update, until `a` recalls it.
+    answer_pinned: bool,
+    /// User-defined macros: name -> body (a line of tokens).
+    pub macros: HashMap<String, String>,
+    /// User-defined operator shortcuts: alias -> operator or macro name.
+    pub aliases: HashMap<String, String>,
+    /// Maximum operator executions per input line (see `oplimit`).
+    pub op_limit: u64,
+    /// Result of every successful line that ran more than read-only commands (see `session`).
+    pub all_answers: Vec<f64>,
+    /// Named copies of the stack, storage and last answer (see `checkpoint`).
+    pub checkpoints: HashMap<String, CalculatorState>,
+    /// Stack snapshots taken before each top-level token, newest last (see `undo`).
+    pub undo_history: Vec<Vec<StackItem>>,
+    /// Snapshots undone since the last new operation, newest last (see `redo`).
+    pub redo_history: Vec<Vec<StackItem>>,
+    ops_this_line: u64,
+    macro_depth: usize,
+    rng: rng::Rng,
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calculator {
+    pub fn new() -> Self {
+        Calculator {
+            stack: Vec::new(),
+            last_answer: None,
+            storage: HashMap::new(),
+            stack_storage: HashMap::new(),
+            list_storage: HashMap::new(),
+            prompt: DEFAULT_PROMPT.to_string(),
+            running_total: None,
+            trace: false,
+            trace_log: Vec::new(),
+            chain: false,
+            step: false,
+            locked: false,
+            last_constant: None,
+            hints: false,
+            sig_track: false,
+            sig_warning: None,
+            sig_figs: Vec::new(),
+            provenance: Vec::new(),
+            last_hint: None,
+            error_detail: None,
+            usage_counts: HashMap::new(),
+            autolog: None,
+            input_base: 10,
+            cast_mode: CastMode::default(),
+            angle_mode: AngleMode::default(),
+            answer_mode: AnswerMode::default(),
+            answer_kept: false,
+            answer_pinned: false,
+            macros: HashMap::new(),
+            aliases: HashMap::new(),
+            op_limit: DEFAULT_OP_LIMIT,
+            all_answers: Vec::new(),
+            checkpoints: HashMap::new(),
+            undo_history: Vec::new(),
+            redo_history: Vec::new(),
+            ops_this_line: 0,
+            macro_depth: 0,
+            rng: rng::Rng::from_time(),
+        }
+    }
 
-    if let Ok(num) = cleaned_token.parse::<f64>() {
-        stack.push(StackItem::Number(num));
-        return Ok(());
+    /// Returns the calculator to the state of a fresh session: empty stack,
+    /// storage, macros and history, and every mode back to its default.
+    /// The startup file is not run again.
+    pub fn reset(&mut self) {
+        // A reset run from a macro or script keeps the nesting that is still
+        // unwinding, and the line keeps counting toward its operation limit
+        let (macro_depth, ops_this_line) = (self.macro_depth, self.ops_this_line);
+        *self = Self::new();
+        self.macro_depth = macro_depth;
+        self.ops_this_line = ops_this_line;
     }
 
-    // 3. Handle Commands and Operators via Centralized Map Lookup
-    if let Some((_group, _usage, action)) = OPERATOR_DATA.get(token) {
-        match action {
-            OperatorAction::PushConstant(val) => {
-                stack.push(StackItem::Number(*val));
-                Ok(())
+    /// The core function to process a single input token.
+    pub fn process_token(&mut self, token: &str) -> Result<(), &'static str> {
+        // 0. A locked stack only accepts read-only commands
+        let read_only = READ_ONLY_TOKENS.contains(&token);
+        if self.locked && !read_only {
+            return Err("stack is locked");
+        }
+        // Anything that may change the stack forgets which constant is on top
+        if !read_only {
+            self.last_constant = None;
+        }
+
+        // 1. Check for Quoted String Key
+        if let Some(key) = quoted_key(token) {
+            self.stack.push(StackItem::Key(key.to_string()));
+            return Ok(());
+        }
+
+        // 2. Handle Numeric Input
+        // In another input base, bare integers use that base (decimal floats are not
+        // accepted). Operator, alias and macro names win over digits, so "c" or "e"
+        // in base 16 still mean clear and Euler's number.
+        let parsed = if self.input_base == 10 {
+            parse_number(token)
+        } else if self.is_command(token) {
+            None
+        } else {
+            let integer = i64::from_str_radix(&clean_number(token), self.input_base);
+            // Beyond 2^53 an f64 would silently round the integer
+            let too_big = match &integer {
+                Ok(n) => n.unsigned_abs() > 1 << 53,
+                Err(e) => matches!(
+                    e.kind(),
+                    IntErrorKind::PosOverflow | IntErrorKind::NegOverflow
+                ),
+            };
+            if too_big {
+                return Err("inbase: integers beyond 2^53 cannot be represented exactly");
             }
-            OperatorAction::Unary(handler) => unary::calculate(stack, *handler),
-            OperatorAction::Binary(handler) => binary::calculate(stack, *handler, token),
-            OperatorAction::Special(name) => {
-                special::handle_special_operator(stack, token, *name, last_answer, storage)
+            integer
+                .ok()
+                .map(|n| n as f64)
+                .or_else(|| parse_duration(token))
+        };
+
+        if let Some(num) = parsed {
+            self.stack.push(StackItem::Number(num));
+            // Running total mode counts entered numbers only, never operator results
+            if let Some(total) = self.running_total.as_mut() {
+                *total += num;
             }
+            return Ok(());
+        }
+
+        // 3. Handle Dice Notation (e.g., 3d6, d20)
+        if let Some((count, sides)) = parse_dice(token) {
+            if !(1..=MAX_DICE).contains(&count) || !(1..=MAX_SIDES).contains(&sides) {
+                return Err("Dice notation needs 1-1000 dice with 1-1000000 sides (e.g., 3d6)");
+            }
+            let total: u64 = (0..count).map(|_| self.rng.roll(sides)).sum();
+            self.stack.push(StackItem::Number(total as f64));
+            return Ok(());
+        }
+
+        // 4. Resolve user aliases (one step only: the target is looked up as an operator or macro)
+        let resolved;
+        let token = match self.aliases.get(token) {
+            Some(target) => {
+                resolved = target.clone();
+                resolved.as_str()
+            }
+            None => token,
+        };
+
+        // 5. Handle Commands and Operators via Centralized Map Lookup
+        if let Some((name, (_group, _usage, action))) = OPERATOR_DATA.get_entry(token) {
+            // Guards against runaway loops; the count restarts with every input line
+            self.ops_this_line += 1;
+            if self.ops_this_line > self.op_limit {
+                return Err("operation limit exceeded");
+            }
+            let result = match action {
+                OperatorAction::PushConstant(val) => {
+                    self.stack.push(StackItem::Number(*val));
+                    self.last_constant = Some(name);
+                    Ok(())
+                }
+                OperatorAction::Unary(handler) => {
+                    let handler = match self.angle_mode {
+                        AngleMode::Degrees => unary::in_degrees(token).unwrap_or(*handler),
+                        AngleMode::Radians => *handler,
+                    };
+                    unary::calculate(&mut self.stack, handler, token)
+                }
+                OperatorAction::Binary(handler) => {
+                    let handler = match self.angle_mode {
+                        AngleMode::Degrees if token == "atan2" => binary::atan2_degrees,
+                        _ => *handler,
+                    };
+                    binary::calculate(&mut self.stack, handler, token)
+                }
+                OperatorAction::Special(name) => {
+                    special::handle_special_operator(self, token, name)
+                }
+            };
+            if result.is_ok() {
+                *self.usage_counts.entry(name).or_insert(0) += 1;
+            }
+            result
+        } else if let Some(body) = self.macros.get(token) {
+            // 6. Handle User-Defined Macros
+            let body = body.clone();
+            self.run_macro(&body)
+        } else {
+            Err("Unrecognized token or operator")
+        }
+    }
+
+    /// Runs every token of a macro body against the current state.
+    pub(crate) fn run_macro(&mut self, body: &str) -> Result<(), &'static str> {
+        if self.macro_depth >= MAX_MACRO_DEPTH {
+            return Err("Macro nesting too deep (recursive macro?)");
+        }
+        self.macro_depth += 1;
+        let result = tokenize(body).into_iter().try_for_each(|token| {
+            let result = self.process_token(token);
+            if self.trace {
+                self.trace_step(token, &result);
+            }
+            result
+        });
+        self.macro_depth -= 1;
+        result
+    }
+
+    /// Processes a full input line: strips the comment, runs every token,
+    /// and updates the last answer if the whole line succeeded.
+    /// Processing stops at the first failing token.
+    /// A line run from a script (`run`) belongs to the line that ran it: it
+    /// shares that line's operation budget and diagnostics, and chain mode
+    /// does not apply to it.
+    pub fn process_line(&mut self, line: &str) -> Result<(), &'static str> {
+        // Check for comment marker (#) and strip the rest of the line
+        let input = line.split('#').next().unwrap_or("").trim();
+        let nested = self.macro_depth > 0;
+
+        // Chain mode starts a line from the previous answer when the stack is
+        // empty, unless the line only toggles modes or displays something
+        if self.chain
+            && !nested
+            && !self.locked
+            && self.stack.is_empty()
+            && tokenize(input).iter().any(|token| {
+                !READ_ONLY_TOKENS.contains(token)
+                    && !matches!(OPERATOR_DATA.get(token), Some(("Meta", _, _)))
+            })
+            && let Some(answer) = self.last_answer
+        {
+            self.stack.push(StackItem::Number(answer));
+            self.answer_pinned = false;
+        }
+
+        if !nested {
+            self.last_hint = None;
+            self.error_detail = None;
+            self.sig_warning = None;
+            self.ops_this_line = 0;
+            self.trace_log.clear();
+        }
+        self.answer_kept = false;
+        let mut computed = false;
+        let tokens = tokenize(input);
+        for (i, &token) in tokens.iter().enumerate() {
+            // "name" help must work on a locked stack, and help consumes the key again
+            let key_for_help = quoted_key(token).is_some() && tokens.get(i + 1) == Some(&"help");
+            let read_only = READ_ONLY_TOKENS.contains(&token) || key_for_help;
+            computed |= !read_only;
+            let before = (!read_only || self.step).then(|| self.stack.clone());
+            let result = if key_for_help && self.locked {
+                self.locked = false;
+                let result = self.process_token(token);
+                self.locked = true;
+                result
+            } else {
+                self.process_token(token)
+            };
+            if let Err(e) = result {
+                // Failed operators restore their operands, so the depth is still accurate
+                if self.hints {
+                    self.last_hint = underflow_hint(token, self.stack.len());
+                }
+                return Err(e);
+            }
+            if let Some(before) = before {
+                if self.sig_track {
+                    self.track_sig_figs(token, &before);
+                }
+                if self.step && self.is_command(token) {
+                    println!("  {}: {}", token, self.step_effect(token, &before));
+                }
+                self.track_provenance(token, &before);
+                // Every successful top-level token is one undo step; a macro counts as one
+                if !read_only && token != "undo" && token != "redo" {
+                    self.record_undo(before);
+                }
+            }
+        }
+
+        // Update Last Answer ONLY if the input line processed successfully
+        if let Some(StackItem::Number(result)) = self.stack.last() {
+            if !self.answer_pinned {
+                self.last_answer = Some(*result);
+            }
+            self.append_to_autolog(*result);
+            if computed {
+                self.all_answers.push(*result);
+            }
+            if self.sig_track {
+                self.check_sig_figs(*result);
+            }
+        } else if self.stack.is_empty()
+            && self.answer_mode == AnswerMode::ClearOnEmpty
+            && !self.answer_kept
+            && !self.answer_pinned
+        {
+            self.last_answer = None;
+        }
+        Ok(())
+    }
+
+    /// Describes what `token` did, given the stack before it ran. Unary and
+    /// binary operators always consume one and two items; for anything else the
+    /// changed part of the stack is whatever follows the unchanged bottom.
+    fn step_effect(&self, token: &str, before: &[StackItem]) -> String {
+        let start = self.changed_from(token, before);
+        describe_effect(&before[start..], &self.stack[start..])
+    }
+
+    /// Index of the first stack item `token` consumed or produced, given the
+    /// stack before it ran (see `step_effect`).
+    fn changed_from(&self, token: &str, before: &[StackItem]) -> usize {
+        let consumed = match OPERATOR_DATA.get(token) {
+            Some((_, _, OperatorAction::Unary(_))) => Some(1),
+            Some((_, _, OperatorAction::Binary(_))) => Some(2),
+            _ => None,
+        };
+        consumed.map(|n| before.len() - n).unwrap_or_else(|| {
+            before
+                .iter()
+                .zip(&self.stack)
+                .take_while(|(old, new)| old == new)
+                .count()
+        })
+    }
+
+    /// Updates `provenance` after one top-level token. Each produced item is
+    /// described by the RPN that made it: its operands' own histories (or
+    /// their values) followed by the token. An item moved by a stack command
+    /// (e.g. `<>`, `rot`) keeps its history. Typed numbers and
+    /// whole-state commands (undo, redo, restore) leave no history.
+    fn track_provenance(&mut self, token: &str, before: &[StackItem]) {
+        self.provenance.resize(before.len(), None);
+        let start = self.changed_from(token, before);
+        let consumed = &before[start..];
+        let consumed_history = self.provenance.split_off(start);
+
+        let restores_state = matches!(token, "undo" | "redo" | "restore");
+        // Only stack commands move items around unchanged; anything else computes
+        // fresh results, even when one happens to equal an operand (2 0 +)
+        let target = self.aliases.get(token).map_or(token, String::as_str);
+        let rearranges = matches!(OPERATOR_DATA.get(target), Some(("Stack", _, _)));
+        let expression = if consumed.is_empty() && !self.is_command(token) {
+            None
+        } else {
+            let mut parts: Vec<String> = consumed
+                .iter()
+                .zip(&consumed_history)
+                .map(|(item, history)| match (item, history) {
+                    (_, Some(history)) => history.clone(),
+                    (StackItem::Number(val), None) => val.to_string(),
+                    (item, None) => format_item(item),
+                })
+                .collect();
+            parts.push(token.to_string());
+            Some(truncate_history(parts.join(" ")))
+        };
+
+        for item in &self.stack[start..] {
+            let passed_through = consumed
+                .iter()
+                .position(|old| old == item)
+                .filter(|_| rearranges);
+            let history = match passed_through {
+                Some(i) => consumed_history[i].clone(),
+                None if restores_state => None,
+                None => expression.clone(),
+            };
+            self.provenance.push(history);
         }
-    } else {
-        Err("Unrecognized token or operator")
+    }
+
+    /// The history of the top item as `expression = value`, or a note that it
+    /// has none (see `explain`).
+    pub fn explain(&self) -> Option<String> {
+        let top = self.stack.last()?;
+        Some(match self.provenance.get(self.stack.len() - 1) {
+            Some(Some(history)) => format!("{} = {}", history, format_item(top)),
+            _ => format!(
+                "{} has no recorded history (typed in, recalled from storage or restored)",
+                format_item(top)
+            ),
+        })
+    }
+
+    /// Updates `sig_figs` after one top-level token. Items below the part of the
+    /// stack the token changed keep their figures; a typed number gets its own
+    /// count, and + - * / combine their operands' counts. Anything else is unknown.
+    fn track_sig_figs(&mut self, token: &str, before: &[StackItem]) {
+        self.sig_figs.resize(before.len(), None);
+        // By arity, so a result equal to its first operand (1.5 1 *) still counts as new
+        let kept = self.changed_from(token, before);
+
+        let figs = match (&before[kept..], &self.stack[kept..]) {
+            ([], [StackItem::Number(_)]) if self.input_base == 10 => {
+                count_sig_figs(&clean_number(token))
+            }
+            ([StackItem::Number(a), StackItem::Number(b)], [StackItem::Number(result)]) => {
+                match (self.sig_figs[kept], self.sig_figs[kept + 1]) {
+                    (Some(fa), Some(fb)) => propagate_sig_figs(token, (*a, fa), (*b, fb), *result),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        self.sig_figs.truncate(kept);
+        self.sig_figs.resize(self.stack.len(), None);
+        if let (Some(figs), Some(last)) = (figs, self.sig_figs.last_mut()) {
+            *last = Some(figs);
+        }
+    }
+
+    /// Sets `sig_warning` if the result on top shows more significant figures
+    /// than its inputs justify.
+    fn check_sig_figs(&mut self, result: f64) {
+        if let (Some(Some(justified)), Some(shown)) =
+            (self.sig_figs.last(), displayed_sig_figs(result))
+            && shown > *justified
+        {
+            self.sig_warning = Some(format!(
+                "{} implies more precision than the inputs justify; to {} significant figures it is {}",
+                result,
+                justified,
+                round_sig(result, *justified)
+            ));
+        }
+    }
+
+    /// Saves the stack as it was before a new operation. As in most editors,
+    /// a new operation discards everything that could have been redone.
+    fn record_undo(&mut self, snapshot: Vec<StackItem>) {
+        if self.undo_history.len() == MAX_UNDO_LEVELS {
+            self.undo_history.remove(0);
+        }
+        self.undo_history.push(snapshot);
+        self.redo_history.clear();
+    }
+
+    /// Records one executed macro token and the resulting stack in `trace_log`
+    /// and prints it to stderr, indented by macro nesting depth.
+    fn trace_step(&mut self, token: &str, result: &Result<(), &'static str>) {
+        let indent = "  ".repeat(self.macro_depth - 1);
+        let step = match result {
+            Ok(()) => format!("{}{} -> {}", indent, token, format_stack(&self.stack)),
+            Err(e) => format!("{}{} -> error: {}", indent, token, e),
+        };
+        eprintln!("trace: {}", step);
+        self.trace_log.push(step);
+    }
+
+    /// True if `token` names an operator, alias or macro.
+    fn is_command(&self, token: &str) -> bool {
+        OPERATOR_DATA.contains_key(token)
+            || self.aliases.contains_key(token)
+            || self.macros.contains_key(token)
+    }
+
+    /// Appends a line result to the autolog file, if one is set. A failed write is
+    /// reported on stderr but does not fail the line.
+    fn append_to_autolog(&self, value: f64) {
+        let Some(path) = &self.autolog else {
+            return;
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", value));
+        if let Err(e) = written {
+            eprintln!("autolog: could not write to '{}': {}", path, e);
+        }
+    }
+
+    /// Operator usage counts, most used first (ties broken by name).
+    pub fn usage_report(&self) -> Vec<(&'static str, u64)> {
+        let mut report: Vec<_> = self.usage_counts.iter().map(|(k, v)| (*k, *v)).collect();
+        report.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        report
+    }
+
+    /// Reseeds the random number generator so that dice rolls become reproducible.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = rng::Rng::from_seed(seed);
     }
 }
 
+/// Processes a single token against a bare stack, last answer and storage.
+/// Session state beyond these (e.g., the random generator) starts fresh on every call.
+pub fn process_token(
+    stack: &mut Vec<StackItem>,
+    token: &str,
+    last_answer: &mut Option<f64>,
+    storage: &mut HashMap<String, f64>,
+) -> Result<(), &'static str> {
+    let mut calc = Calculator {
+        stack: std::mem::take(stack),
+        last_answer: *last_answer,
+        storage: std::mem::take(storage),
+        ..Calculator::new()
+    };
+    let result = calc.process_token(token);
+    *stack = calc.stack;
+    *last_answer = calc.last_answer;
+    *storage = calc.storage;
+    result
+}
+
+/// Name of the startup file, looked up in the home directory.
+pub const CONFIG_FILE_NAME: &str = ".kalkrc";
+
+/// Runs every line of a config file through `process_line`, as if typed at the
+/// prompt. A failing line prints a warning and loading carries on; only I/O
+/// errors (e.g., a missing file) are returned.
+pub fn load_config(path: &Path, calc: &mut Calculator) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    for (number, line) in contents.lines().enumerate() {
+        if let Err(e) = calc.process_line(line) {
+            let message = calc.error_detail.as_deref().unwrap_or(e);
+            eprintln!(
+                "Warning: {} line {}: {}",
+                path.display(),
+                number + 1,
+                message
+            );
+        }
+    }
+    Ok(())
+}
+
 pub fn main_app_loop() {
-    let mut stack: Vec<StackItem> = Vec::new();
-    let mut last_answer: Option<f64> = None;
-    let mut storage: HashMap<String, f64> = HashMap::new();
+    let mut calc = Calculator::new();
+
+    // Load ~/.kalkrc, if present, before the first prompt
+    if let Some(home) = env::var_os("HOME") {
+        let path = Path::new(&home).join(CONFIG_FILE_NAME);
+        match load_config(&path, &mut calc) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Warning: could not read {}: {}", path.display(), e),
+        }
+    }
 
     println!("Welcome to kalk-rs (RPN Calculator). Type 'exit' to quit.");
     println!("Type 'help' for a list of all functions or '\"func\" help' for specific usage.");
 
     loop {
-        // Manually format the stack for a cleaner look.
-        let display_content: Vec<String> = stack
-            .iter()
-            .map(|item| {
-                match item {
-                    StackItem::Number(val) => val.separate_with_commas(),
-                    // Display keys surrounded by their quotes
-                    StackItem::Key(key) => format!("\"{}\"", key),
-                }
-            })
-            .collect();
-
-        // Join the items and wrap in square brackets
-        let display_string = format!("[{}]", display_content.join(", "));
-
-        // Display the current stack state using the new display_string
-        print!("Stack: {}\n> ", display_string);
+        // Display the current stack state using the prompt template
+        if let Some(total) = calc.running_total {
+            println!("Total: {}", total.separate_with_commas());
+        }
+        print!(
+            "{}",
+            render_prompt(&calc.prompt, &calc.stack, calc.last_answer)
+        );
 
         io::stdout().flush().unwrap();
 
@@ -198,29 +1323,19 @@ pub fn main_app_loop() {
         }
 
         // Check for comment marker (#) and strip the rest of the line
-        let input = input.splitn(2, '#').next().unwrap_or("").trim();
+        let input = input.split('#').next().unwrap_or("").trim();
 
         if input.eq_ignore_ascii_case("exit") {
             break;
         }
 
-        // Process tokens
-        let mut tokens = input.split_whitespace();
-        let mut success = true;
-
-        while let Some(token) = tokens.next() {
-            if let Err(e) = process_token(&mut stack, token, &mut last_answer, &mut storage) {
-                eprintln!("Error: {}", e);
-                // On error, clear the current input line's processing
-                success = false;
-                break;
-            }
-        }
-        // Update Last Answer ONLY if the input line processed successfully
-        if success {
-            if let Some(StackItem::Number(result)) = stack.last() {
-                last_answer = Some(*result);
+        if let Err(e) = calc.process_line(input) {
+            eprintln!("Error: {}", calc.error_detail.as_deref().unwrap_or(e));
+            if let Some(hint) = &calc.last_hint {
+                eprintln!("Hint: {}", hint);
             }
+        } else if let Some(warning) = &calc.sig_warning {
+            eprintln!("Warning: {}", warning);
         }
     }
 }
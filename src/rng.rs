@@ -0,0 +1,35 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A small xorshift64* pseudo-random generator.
+/// Good enough for dice rolls; not suitable for anything security related.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Creates a generator from a fixed seed. The same seed always gives the same sequence.
+    pub fn from_seed(seed: u64) -> Self {
+        // xorshift gets stuck on an all-zero state, so mix in a fixed odd constant.
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    /// Creates a generator seeded from the system clock.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Rng::from_seed(nanos)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a value in 1..=sides.
+    pub fn roll(&mut self, sides: u64) -> u64 {
+        self.next_u64() % sides + 1
+    }
+}
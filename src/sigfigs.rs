@@ -0,0 +1,63 @@
+/// Counts the significant figures in a typed number, e.g. "0.0450" -> 3.
+/// Leading zeros never count; trailing zeros count only after a decimal point,
+/// so "1200" has 2 but "1200." has 4. A zero value counts as 1.
+/// Returns None if the token is not a plain decimal number.
+pub fn count_sig_figs(token: &str) -> Option<u32> {
+    let unsigned = token.strip_prefix(['+', '-']).unwrap_or(token);
+    let mantissa = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => {
+            exponent.parse::<i32>().ok()?;
+            mantissa
+        }
+        None => unsigned,
+    };
+
+    let has_point = mantissa.contains('.');
+    let valid = mantissa.matches('.').count() <= 1
+        && mantissa.chars().all(|c| c == '.' || c.is_ascii_digit())
+        && mantissa.chars().any(|c| c.is_ascii_digit());
+    if !valid {
+        return None;
+    }
+
+    let digits: String = mantissa.chars().filter(char::is_ascii_digit).collect();
+    let digits = digits.trim_start_matches('0');
+    let digits = if has_point {
+        digits
+    } else {
+        digits.trim_end_matches('0')
+    };
+    Some(digits.len().max(1) as u32)
+}
+
+/// Significant figures shown when x is displayed, e.g. 13.534 -> 5.
+/// None for infinities and NaN.
+pub fn displayed_sig_figs(x: f64) -> Option<u32> {
+    count_sig_figs(&x.to_string())
+}
+
+/// Power of ten of the last significant digit of x, e.g. 12.3 with 3 figures -> -1.
+fn last_digit_place(x: f64, figs: u32) -> i32 {
+    let magnitude = if x == 0.0 {
+        0
+    } else {
+        x.abs().log10().floor() as i32
+    };
+    magnitude + 1 - figs as i32
+}
+
+/// Significant figures justified for `a op b = result`, given the figures of
+/// the operands. `*` and `/` keep the smaller count; `+` and `-` keep the
+/// coarser last decimal place, so 12.3 + 1.234 = 13.534 justifies 3 (13.5).
+/// Returns None for any other operator.
+pub fn propagate_sig_figs(op: &str, a: (f64, u32), b: (f64, u32), result: f64) -> Option<u32> {
+    match op {
+        "*" | "/" => Some(a.1.min(b.1)),
+        "+" | "-" => {
+            let place = last_digit_place(a.0, a.1).max(last_digit_place(b.0, b.1));
+            let figs = last_digit_place(result, 1) + 1 - place;
+            Some(figs.max(1) as u32)
+        }
+        _ => None,
+    }
+}
@@ -1,33 +1,396 @@
-use super::{OPERATOR_DATA, StackItem};
+use super::{
+    AngleMode, AnswerMode, Calculator, CalculatorState, CastMode, OPERATOR_DATA, OperatorAction,
+    PHYSICAL_UNITS, StackItem, restore_operands, stats, to_integer,
+};
+use phf::{Map, phf_map};
 use std::collections::HashMap;
 
 pub fn handle_special_operator(
-    stack: &mut Vec<StackItem>,
+    calc: &mut Calculator,
     token: &str,
     special_name: &'static str,
-    last_answer: &mut Option<f64>,
-    storage: &mut HashMap<String, f64>,
 ) -> Result<(), &'static str> {
+    let stack = &mut calc.stack;
     match special_name {
         "factorial" => crate::special::factorial(stack),
         "permutations" => crate::special::permutations(stack),
         "combinations" => crate::special::combinations(stack),
+        "factratio" => crate::special::factorial_ratio(stack),
+        "simplify" => crate::special::simplify(stack),
+        "digitsum" => crate::special::digits_of(stack, false),
+        "digitalroot" => crate::special::digits_of(stack, true),
+        "make_complex" => crate::special::make_complex(stack),
+        "imaginary" => crate::special::imaginary(stack),
+        "cis" => crate::special::cis(stack, calc.angle_mode),
+        "angle_mode" => {
+            let mode = match stack.last() {
+                Some(StackItem::Key(name)) => match name.as_str() {
+                    "deg" => AngleMode::Degrees,
+                    "rad" => AngleMode::Radians,
+                    _ => return Err("anglemode must be \"deg\" or \"rad\""),
+                },
+                _ => return Err("anglemode requires a mode key (\"deg\" or \"rad\") on the stack"),
+            };
+            stack.pop();
+            calc.angle_mode = mode;
+            Ok(())
+        }
+        "normalize_angle" => crate::special::normalize_angle(stack, calc.angle_mode),
+        "day_of_week" => crate::special::dow(stack),
+        "geomean" => stats::reduce_run(
+            stack,
+            stats::geometric_mean,
+            "geomean requires positive numbers on the stack",
+        ),
+        "min_all" => stats::reduce_run(
+            stack,
+            stats::minimum,
+            "minall requires at least one number on the stack",
+        ),
+        "max_all" => stats::reduce_run(
+            stack,
+            stats::maximum,
+            "maxall requires at least one number on the stack",
+        ),
+        "harmean" => stats::reduce_run(
+            stack,
+            stats::harmonic_mean,
+            "harmean requires non-zero numbers on the stack",
+        ),
+        "sumsq" => stats::reduce_run(
+            stack,
+            stats::sum_of_squares,
+            "sumsq requires at least one number on the stack",
+        ),
+        "mode" => stats::reduce_run(
+            stack,
+            stats::statistical_mode,
+            "mode requires at least one number on the stack",
+        ),
+        "rms" => stats::reduce_run(
+            stack,
+            stats::root_mean_square,
+            "rms requires at least one number on the stack",
+        ),
         "swap" => crate::special::swap(stack),
+        "range" => crate::special::range(stack),
+        "luhn" => crate::special::luhn(stack),
+        "rootn" => crate::special::exact_root(stack),
+        "linfit" => crate::special::linfit(stack),
+        "diff" => crate::special::differences(stack),
+        "minmaxnorm" => crate::special::min_max_normalize(stack),
+        "countgt" => crate::special::count_compare(stack, std::cmp::Ordering::Greater),
+        "countlt" => crate::special::count_compare(stack, std::cmp::Ordering::Less),
+        "counteq" => crate::special::count_compare(stack, std::cmp::Ordering::Equal),
+        "zscore" => crate::special::zscore(stack),
+        "cumsum" => crate::special::cumulative_sum(stack),
+        "det2" => crate::special::det2(stack),
+        "pcterr" => crate::special::percent_error(stack),
+        "logb" => crate::special::log_base(stack),
+        "between" => crate::special::between(stack),
+        "sigfig" => crate::special::sigfig(stack),
+        "rot" => crate::special::rot(stack),
+        "rot_back" => crate::special::rot_back(stack),
+        "sink" => crate::special::sink(stack),
+        "float" => crate::special::float(stack),
+        "bury" => crate::special::bury(stack),
+        "swapblock" => crate::special::swap_block(stack),
+        "keep" => crate::special::keep(stack),
+        "dedup" => crate::special::dedup(stack),
+        "sort" => crate::special::sort(stack),
+        "peek_at" => crate::special::peek_at(stack),
+        "swap_numbers_only" => crate::special::swap_numbers_only(stack),
+        "undo" => match calc.undo_history.pop() {
+            Some(previous) => {
+                let current = std::mem::replace(stack, previous);
+                calc.redo_history.push(current);
+                Ok(())
+            }
+            None => Err("Nothing to undo"),
+        },
+        "redo" => match calc.redo_history.pop() {
+            Some(next) => {
+                let current = std::mem::replace(stack, next);
+                calc.undo_history.push(current);
+                Ok(())
+            }
+            None => Err("Nothing to redo"),
+        },
         "clear" => {
             stack.clear();
             Ok(())
         }
+        "capture" => match stack.last() {
+            Some(StackItem::Number(val)) => {
+                // Pinned, so later line results do not replace it before 'a' recalls it
+                calc.last_answer = Some(*val);
+                calc.answer_pinned = true;
+                Ok(())
+            }
+            _ => Err("capture requires a number on top of the stack"),
+        },
+        "soft_clear" => {
+            // Keep the top number recoverable with 'a'
+            if let Some(StackItem::Number(val)) = stack.last() {
+                calc.last_answer = Some(*val);
+                calc.answer_kept = true;
+            }
+            stack.clear();
+            Ok(())
+        }
         "answer" => {
-            if let Some(val) = *last_answer {
+            if let Some(val) = calc.last_answer {
+                // "-a" is shorthand for "a -1 *"
+                let val = if token == "-a" { -val } else { val };
                 stack.push(StackItem::Number(val));
+                calc.answer_pinned = false;
                 Ok(())
             } else {
                 Err("No previous answer available ('a' is empty)")
             }
         }
-        "store" => crate::special::store(stack, storage),
-        "recall" => crate::special::recall(stack, storage),
-        "display_base" => display_base(stack, token),
+        "swap_answer" => {
+            crate::special::swap_with_answer(stack, &mut calc.last_answer)?;
+            calc.answer_pinned = true;
+            Ok(())
+        }
+        "store" => crate::special::store(stack, &mut calc.storage),
+        "recall" => crate::special::recall(stack, &calc.storage),
+        "store_swap" => crate::special::store_swap(stack, &mut calc.storage),
+        "store_if" => crate::special::store_if(stack, &mut calc.storage),
+        "save_stack" => crate::special::save_stack(stack, &mut calc.stack_storage),
+        "load_stack" => crate::special::load_stack(stack, &calc.stack_storage),
+        "pack" => crate::special::pack(stack, &mut calc.list_storage),
+        "unpack" => crate::special::unpack(stack, &calc.list_storage),
+        "seed" => crate::special::seed(calc),
+        "run" => crate::special::run_file(calc),
+        "map" => crate::special::map(calc),
+        "zipwith" => crate::special::zip_with(calc),
+        "set_prompt" => match stack.pop() {
+            Some(StackItem::Key(template)) => {
+                calc.prompt = if template == "default" {
+                    crate::DEFAULT_PROMPT.to_string()
+                } else {
+                    // Keys cannot contain real newlines, so accept the \n escape.
+                    template.replace("\\n", "\n")
+                };
+                Ok(())
+            }
+            item => {
+                restore_operands(stack, item.as_slice());
+                Err("prompt requires a string key template (e.g., \"{depth}> \")")
+            }
+        },
+        "toggle_total" => {
+            calc.running_total = match calc.running_total {
+                Some(_) => None,
+                None => Some(0.0),
+            };
+            Ok(())
+        }
+        "export" => crate::special::export_csv(stack),
+        "autolog" => match stack.pop() {
+            Some(StackItem::Key(path)) => {
+                calc.autolog = if path == "off" { None } else { Some(path) };
+                Ok(())
+            }
+            item => {
+                restore_operands(stack, item.as_slice());
+                Err("autolog requires a file name key (or \"off\") on the stack")
+            }
+        },
+        "alias" => crate::special::alias(calc),
+        "profile" => {
+            println!("\nOperator usage:");
+            for (op, count) in calc.usage_report() {
+                println!("  {:<10} {}", op, count);
+            }
+            Ok(())
+        }
+        "explain" => {
+            let text = calc
+                .explain()
+                .ok_or("explain requires an item on the stack")?;
+            println!("\n{}", text);
+            Ok(())
+        }
+        "session" => {
+            let summary =
+                session_summary(&calc.all_answers).ok_or("session: no line results yet")?;
+            println!("\n{}", summary);
+            Ok(())
+        }
+        "lock" => {
+            calc.locked = true;
+            Ok(())
+        }
+        "unlock" => {
+            calc.locked = false;
+            Ok(())
+        }
+        "checkpoint" => crate::special::checkpoint(calc),
+        "restore" => crate::special::restore(calc),
+        "reset" => {
+            calc.reset();
+            Ok(())
+        }
+        "toggle_sigtrack" => {
+            calc.sig_track = !calc.sig_track;
+            calc.sig_figs.clear();
+            Ok(())
+        }
+        "toggle_step" => {
+            calc.step = !calc.step;
+            Ok(())
+        }
+        "toggle_trace" => {
+            calc.trace = !calc.trace;
+            Ok(())
+        }
+        "toggle_chain" => {
+            calc.chain = !calc.chain;
+            Ok(())
+        }
+        "toggle_hints" => {
+            calc.hints = !calc.hints;
+            Ok(())
+        }
+        "show_total" => match calc.running_total {
+            Some(total) => {
+                println!("\nTotal: {}", total);
+                Ok(())
+            }
+            None => Err("Running total mode is off (use 'total' to turn it on)"),
+        },
+        "clear_total" => match calc.running_total.as_mut() {
+            Some(total) => {
+                *total = 0.0;
+                Ok(())
+            }
+            None => Err("Running total mode is off (use 'total' to turn it on)"),
+        },
+        "define" => crate::special::define(calc),
+        "do_while" => crate::special::do_while(calc),
+        "display_base" => display_base(stack, token, calc.cast_mode),
+        "peek_angle" => peek_angle(stack, token),
+        "roman" => {
+            let roman = match stack.last() {
+                Some(StackItem::Number(val)) => to_integer(*val, CastMode::Round)
+                    .and_then(|n| u32::try_from(n).ok())
+                    .and_then(to_roman)
+                    .ok_or("roman requires a number from 1 to 3999")?,
+                _ => return Err("roman requires one number on the stack"),
+            };
+            println!("\n{}", roman);
+            Ok(())
+        }
+        "histogram" => {
+            let run = stats::numeric_run_len(stack);
+            if run == 0 {
+                return Err("hist requires at least one number on the stack");
+            }
+            let values: Vec<f64> = stack[stack.len() - run..]
+                .iter()
+                .filter_map(|item| match item {
+                    StackItem::Number(val) => Some(*val),
+                    _ => None,
+                })
+                .collect();
+            print!("\n{}", render_histogram(&values, HISTOGRAM_WIDTH));
+            Ok(())
+        }
+        "percent" => crate::special::display_percent(stack),
+        "join" => crate::special::join(stack),
+        "words" => {
+            let n = match stack.last() {
+                Some(StackItem::Number(val)) => to_integer(*val, CastMode::Round)
+                    .ok_or("words requires a finite number within the i64 range")?,
+                _ => return Err("words requires one number on the stack"),
+            };
+            println!("\n{}", number_to_words(n));
+            Ok(())
+        }
+        "units" => match calc.last_constant {
+            Some(name) => match crate::unit_of(name) {
+                Some(unit) => {
+                    println!("\n{}: {}", name, unit);
+                    Ok(())
+                }
+                None => Err("units?: this constant has no unit"),
+            },
+            None => Err("units?: the top value was not just pushed by a physical constant"),
+        },
+        "constants" => {
+            println!("\n--- Physical Constants (SI) ---");
+            for (name, unit) in PHYSICAL_UNITS {
+                if let Some((_, _, OperatorAction::PushConstant(val))) = OPERATOR_DATA.get(name) {
+                    println!("  {:<10} {:e} {}", name, val, unit);
+                }
+            }
+            Ok(())
+        }
+        "input_base" => {
+            // A quoted base is always read in decimal, so "10" inbase resets from any base
+            let base = match stack.last() {
+                Some(StackItem::Number(val)) => Some(*val),
+                Some(StackItem::Key(key)) => key.parse::<f64>().ok(),
+                _ => None,
+            };
+            match base {
+                Some(b) if (2.0..=36.0).contains(&b) && b.fract() == 0.0 => {
+                    stack.pop();
+                    calc.input_base = b as u32;
+                    Ok(())
+                }
+                _ => Err("inbase requires a base from 2 to 36 (e.g., 16 inbase, \"10\" inbase)"),
+            }
+        }
+        "op_limit" => {
+            let [n] = pop_numbers(stack).ok_or("oplimit requires a number on the stack")?;
+            if n < 1.0 || n.fract() != 0.0 {
+                stack.push(StackItem::Number(n));
+                return Err("oplimit must be a positive integer");
+            }
+            calc.op_limit = n as u64;
+            Ok(())
+        }
+        "answer_mode" => {
+            let mode = match stack.last() {
+                Some(StackItem::Key(name)) => match name.as_str() {
+                    "persist" => AnswerMode::Persist,
+                    "clear" => AnswerMode::ClearOnEmpty,
+                    _ => return Err("ansmode must be \"persist\" or \"clear\""),
+                },
+                _ => {
+                    return Err(
+                        "ansmode requires a mode key (\"persist\" or \"clear\") on the stack",
+                    );
+                }
+            };
+            stack.pop();
+            calc.answer_mode = mode;
+            Ok(())
+        }
+        "cast_mode" => {
+            let mode = match stack.last() {
+                Some(StackItem::Key(name)) => match name.as_str() {
+                    "trunc" => CastMode::Trunc,
+                    "round" => CastMode::Round,
+                    "floor" => CastMode::Floor,
+                    "ceil" => CastMode::Ceil,
+                    _ => {
+                        return Err("castmode must be \"trunc\", \"round\", \"floor\" or \"ceil\"");
+                    }
+                },
+                _ => return Err("castmode requires a mode key on the stack"),
+            };
+            stack.pop();
+            calc.cast_mode = mode;
+            Ok(())
+        }
+        "describe" => describe(stack),
+        "assert" => crate::special::assert_equal(stack, &mut calc.error_detail),
+        "from_digits" => crate::special::from_digits(stack),
         "help" => {
             // Custom RPN help logic
             let target_item = stack.pop();
@@ -35,61 +398,460 @@ pub fn handle_special_operator(
                 Some(StackItem::Key(key)) => {
                     let func_name = key.trim_matches('"').to_lowercase();
                     if crate::OPERATOR_DATA.contains_key(func_name.as_str()) {
-                        display_help(func_name.as_str())
+                        display_help(func_name.as_str(), calc.angle_mode)
                     } else {
                         // Put the key back if it wasn't a function name
                         stack.push(StackItem::Key(key));
-                        display_help("") // Show general help
+                        display_help("", calc.angle_mode) // Show general help
                     }
                 }
-                Some(StackItem::Number(val)) => {
-                    // Put the number back as it's not a function name
-                    stack.push(StackItem::Number(val));
-                    display_help("") // Show general help
+                Some(item) => {
+                    // Put the item back as it's not a function name
+                    stack.push(item);
+                    display_help("", calc.angle_mode) // Show general help
                 }
-                None => display_help(""), // Show general help
+                None => display_help("", calc.angle_mode), // Show general help
             }
         }
         _ => Err("Internal operator error (Special command missing handler)"),
     }
 }
 
+/// Formats the integer part of a (converted per `mode`) in the base named by
+/// `token` ("hex", "oct" or "bin"), with its prefix, e.g. `0xFF`.
+pub fn format_base(a: f64, token: &str, mode: CastMode) -> Result<String, &'static str> {
+    let int_val = to_integer(a, mode).ok_or("Base conversion: value is out of the i64 range")?;
+    match token {
+        "hex" => Ok(format!("0x{:X}", int_val)),
+        "oct" => Ok(format!("0o{:o}", int_val)),
+        "bin" => Ok(format!("0b{:b}", int_val)),
+        _ => Err("Invalid base token"),
+    }
+}
+
 /// Reads the last f64, casts it to i64, prints it in the given base.
 /// The stack is NOT modified.
-fn display_base(stack: &mut Vec<StackItem>, token: &str) -> Result<(), &'static str> {
+fn display_base(stack: &[StackItem], token: &str, mode: CastMode) -> Result<(), &'static str> {
     // 1. Check stack and get number (read-only access)
     let a = match stack.last() {
         Some(StackItem::Number(val)) => *val,
         _ => return Err("Base conversion requires one number on the stack"),
     };
 
-    // 2. Cast to integer (truncates fractional part)
-    let int_val = a as i64;
-    let (prefix, base_str) = match token {
-        "hex" => ("0x", format!("{:X}", int_val)),
-        "oct" => ("0o", format!("{:o}", int_val)),
-        "bin" => ("0b", format!("{:b}", int_val)),
-        _ => return Err("Invalid base token"),
+    // 2. Convert to an integer and print the result outside the stack
+    println!("\n{} Base: {}", token, format_base(a, token, mode)?);
+
+    Ok(())
+}
+
+/// Prints the top number converted to degrees (`?deg`) or radians (`?rad`)
+/// without changing the stack.
+fn peek_angle(stack: &[StackItem], token: &str) -> Result<(), &'static str> {
+    let a = match stack.last() {
+        Some(StackItem::Number(val)) => *val,
+        _ => return Err("Angle peek requires one number on the stack"),
+    };
+
+    match token {
+        "?deg" => println!("\n{} rad = {} deg", a, crate::unary::rad_to_deg(a)),
+        "?rad" => println!("\n{} deg = {} rad", a, crate::unary::deg_to_rad(a)),
+        _ => return Err("Invalid angle peek token"),
+    }
+
+    Ok(())
+}
+
+/// One-line summary of the line results of a session, e.g.
+/// `3 results: sum 6, mean 2, min 1, max 3`. None if there are no results.
+pub fn session_summary(answers: &[f64]) -> Option<String> {
+    let sum: f64 = answers.iter().sum();
+    Some(format!(
+        "{} results: sum {}, mean {}, min {}, max {}",
+        answers.len(),
+        sum,
+        sum / answers.len() as f64,
+        stats::minimum(answers)?,
+        stats::maximum(answers)?
+    ))
+}
+
+/// Joins numbers with a separator, each formatted as on the stack display.
+/// The two-character escapes `\t` and `\n` in the separator stand for a tab
+/// and a newline, since quoted keys cannot hold them directly.
+///
+/// Example: [1, 2, 3] with ", " -> "1, 2, 3"
+pub fn join_numbers(values: &[f64], separator: &str) -> String {
+    let separator = separator.replace("\\t", "\t").replace("\\n", "\n");
+    let formatted: Vec<String> = values
+        .iter()
+        .map(|val| crate::format_item(&StackItem::Number(*val)))
+        .collect();
+    formatted.join(&separator)
+}
+
+/// Pops a separator key and prints the numeric run below it joined by that
+/// separator. The numbers stay on the stack.
+/// Expected stack order: [..., x1, .., xn, "separator"]
+fn join(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let separator = match stack.as_slice() {
+        [.., StackItem::Number(_), StackItem::Key(separator)] => separator.clone(),
+        _ => return Err("join requires numbers and then a separator key (e.g., \", \")"),
+    };
+    stack.pop();
+    let start = stack.len() - stats::numeric_run_len(stack);
+    let values: Vec<f64> = stack[start..]
+        .iter()
+        .map(|item| match item {
+            StackItem::Number(val) => *val,
+            _ => unreachable!("numeric run only contains numbers"),
+        })
+        .collect();
+    println!("\n{}", join_numbers(&values, &separator));
+    Ok(())
+}
+
+/// Decimal places shown by `?pct`.
+const PERCENT_PRECISION: usize = 2;
+
+/// Formats a ratio as a percentage, e.g. 0.1523 with precision 2 -> "15.23%".
+pub fn format_percent(x: f64, precision: usize) -> String {
+    format!("{:.*}%", precision, x * 100.0)
+}
+
+/// Prints the top number as a percentage without changing the stack.
+fn display_percent(stack: &[StackItem]) -> Result<(), &'static str> {
+    match stack.last() {
+        Some(StackItem::Number(val)) => {
+            println!("\n{}", format_percent(*val, PERCENT_PRECISION));
+            Ok(())
+        }
+        _ => Err("?pct requires one number on the stack"),
+    }
+}
+
+/// Width in columns of the longest bar drawn by `hist`.
+const HISTOGRAM_WIDTH: usize = 40;
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const SCALES: [&str; 7] = [
+    "",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+];
+
+/// Words for 1..=999, e.g. "three hundred forty-two".
+fn hundreds_to_words(n: u64) -> String {
+    let mut parts = Vec::new();
+    if n >= 100 {
+        parts.push(format!("{} hundred", ONES[(n / 100) as usize]));
+    }
+    match n % 100 {
+        0 => {}
+        r @ 1..=19 => parts.push(ONES[r as usize].to_string()),
+        r if r % 10 == 0 => parts.push(TENS[(r / 10) as usize].to_string()),
+        r => parts.push(format!(
+            "{}-{}",
+            TENS[(r / 10) as usize],
+            ONES[(r % 10) as usize]
+        )),
+    }
+    parts.join(" ")
+}
+
+/// Spells out an integer in English, e.g. 1234 -> "one thousand two hundred thirty-four".
+pub fn number_to_words(n: i64) -> String {
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+
+    let mut rest = n.unsigned_abs();
+    let mut groups = Vec::new();
+    for scale in SCALES {
+        let group = rest % 1000;
+        if group != 0 {
+            let words = hundreds_to_words(group);
+            groups.push(if scale.is_empty() {
+                words
+            } else {
+                format!("{} {}", words, scale)
+            });
+        }
+        rest /= 1000;
+        if rest == 0 {
+            break;
+        }
+    }
+    groups.reverse();
+
+    let words = groups.join(" ");
+    if n < 0 {
+        format!("negative {}", words)
+    } else {
+        words
+    }
+}
+
+/// Roman numeral for 1..=3999, e.g. 1994 -> "MCMXCIV". None outside that range.
+pub fn to_roman(n: u32) -> Option<String> {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+    if !(1..=3999).contains(&n) {
+        return None;
+    }
+    let mut rest = n;
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while rest >= value {
+            roman.push_str(numeral);
+            rest -= value;
+        }
+    }
+    Some(roman)
+}
+
+/// Renders one horizontal bar per value, scaled so the largest magnitude spans
+/// `width` columns. Bars start at zero: positive values use `#`, negative ones `-`.
+/// If every value is zero all bars are empty.
+pub fn render_histogram(values: &[f64], width: usize) -> String {
+    let labels: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+    let max = values.iter().fold(0.0_f64, |m, v| m.max(v.abs()));
+
+    values
+        .iter()
+        .zip(&labels)
+        .map(|(val, label)| {
+            let len = if max > 0.0 {
+                (val.abs() / max * width as f64).round() as usize
+            } else {
+                0
+            };
+            let fill = if *val < 0.0 { "-" } else { "#" };
+            format!("{:>w$} | {}\n", label, fill.repeat(len), w = label_width)
+        })
+        .collect()
+}
+
+/// Interprets a key as a string of digits in a given base and pushes its value.
+///
+/// Expected stack order: [..., "digits", base]
+pub fn from_digits(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let (digits, base) = match stack.as_slice() {
+        [.., StackItem::Key(digits), StackItem::Number(base)] => (digits.clone(), *base),
+        _ => return Err("fromdigits requires a key of digits and a base (e.g., \"FF\" 16)"),
+    };
+
+    if base.fract() != 0.0 || !(2.0..=36.0).contains(&base) {
+        return Err("fromdigits: base must be an integer from 2 to 36");
+    }
+    let value = i64::from_str_radix(&digits, base as u32)
+        .map_err(|_| "fromdigits: invalid digit for base")?;
+
+    stack.truncate(stack.len() - 2);
+    stack.push(StackItem::Number(value as f64));
+    Ok(())
+}
+
+/// Pops the top N items if they are all numbers, returning them in stack order
+/// (deepest first). The stack is left untouched otherwise.
+fn pop_numbers<const N: usize>(stack: &mut Vec<StackItem>) -> Option<[f64; N]> {
+    let start = stack.len().checked_sub(N)?;
+    let mut values = [0.0; N];
+    for (slot, item) in values.iter_mut().zip(&stack[start..]) {
+        match item {
+            StackItem::Number(val) => *slot = *val,
+            _ => return None,
+        }
+    }
+    stack.truncate(start);
+    Some(values)
+}
+
+/// Returns the day of the week for a Gregorian date using Zeller's congruence.
+/// 0 = Sunday, 1 = Monday, ..., 6 = Saturday.
+pub fn day_of_week(y: i64, m: i64, d: i64) -> u32 {
+    // The calendar repeats every 400 years (146097 days, a whole number of weeks),
+    // so reducing the year first keeps the arithmetic small and positive for any input.
+    let y = 400 + y.rem_euclid(400);
+    // Zeller treats January and February as months 13 and 14 of the previous year.
+    let (m, y) = if m < 3 { (m + 12, y - 1) } else { (m, y) };
+    let k = y.rem_euclid(100);
+    let j = y.div_euclid(100);
+    let h = (d + 13 * (m + 1) / 5 + k + k / 4 + j / 4 + 5 * j).rem_euclid(7);
+    // Zeller's h is 0 = Saturday; shift so that 0 = Sunday.
+    ((h + 6) % 7) as u32
+}
+
+fn days_in_month(y: i64, m: i64) -> i64 {
+    match m {
+        4 | 6 | 9 | 11 => 30,
+        2 if (y % 4 == 0 && y % 100 != 0) || y % 400 == 0 => 29,
+        2 => 28,
+        _ => 31,
+    }
+}
+
+/// Pops a date (year, month, day) and pushes its day of the week (0 = Sunday).
+pub fn dow(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [year, month, day] = match pop_numbers(stack) {
+        Some(values) => values,
+        None => return Err("dow requires three numbers (year, month, day) on the stack"),
     };
 
-    // 3. Print the result outside the stack
-    println!("\n{} Base: {}{}", token, prefix, base_str);
+    let (y, m, d) = (year as i64, month as i64, day as i64);
+    let is_whole = [year, month, day].iter().all(|v| v.fract() == 0.0);
+    // Beyond 2^53 years are not exact, and the i64 cast would saturate
+    if !(-MAX_EXACT_INT..=MAX_EXACT_INT).contains(&year) {
+        stack.extend([year, month, day].map(StackItem::Number));
+        return Err("dow: year must be an integer of at most 2^53 in magnitude");
+    }
+    if !is_whole || !(1..=12).contains(&m) || d < 1 || d > days_in_month(y, m) {
+        stack.extend([year, month, day].map(StackItem::Number));
+        return Err("dow: invalid date (month must be 1-12 and day must exist in that month)");
+    }
+
+    stack.push(StackItem::Number(day_of_week(y, m, d) as f64));
+    Ok(())
+}
+
+/// Prints the type and value of the top item, with a classification for numbers.
+/// The stack is NOT modified.
+pub fn describe(stack: &[StackItem]) -> Result<(), &'static str> {
+    match stack.last() {
+        Some(StackItem::Number(val)) => {
+            let class = if val.is_nan() {
+                "NaN"
+            } else if val.is_infinite() {
+                "infinite"
+            } else if val.fract() == 0.0 && *val < 0.0 {
+                "negative integer"
+            } else if val.fract() == 0.0 {
+                "integer"
+            } else if *val < 0.0 {
+                "negative"
+            } else {
+                "real"
+            };
+            println!("\nNumber: {} ({})", val, class);
+        }
+        Some(StackItem::Key(key)) => println!("\nKey: \"{}\"", key),
+        Some(StackItem::Complex(re, im)) => {
+            println!("\nComplex: {}", crate::complex::format((*re, *im)))
+        }
+        None => return Err("type requires one item on the stack"),
+    }
 
     Ok(())
 }
 
+/// Operators whose angle unit follows the angle mode (reworded by `help_text`).
+const ANGLE_TOKENS: [&str; 8] = ["sin", "cos", "tan", "asin", "acos", "atan", "atan2", "cis"];
+
+/// Usage string for `token` as shown by help, adjusted to the angle mode.
+/// None if the token is not an operator.
+pub fn help_text(token: &str, mode: AngleMode) -> Option<String> {
+    let (_, usage, _) = OPERATOR_DATA.get(token)?;
+    if mode == AngleMode::Degrees && ANGLE_TOKENS.contains(&token) {
+        Some(usage.replace("radians", "degrees"))
+    } else {
+        Some(usage.to_string())
+    }
+}
+
+/// Worked examples shown by `help` for a core set of operators, written as
+/// `input -> resulting stack`.
+const HELP_EXAMPLES: Map<&'static str, &'static str> = phf_map! {
+    "+" => "3 4 + -> 7",
+    "-" => "10 4 - -> 6",
+    "*" => "6 7 * -> 42",
+    "/" => "7 2 / -> 3.5",
+    "**" => "2 8 ** -> 256",
+    "%" => "-7 3 % -> 2",
+    "%%" => "50 75 %% -> 50",
+    "log" => "100 10 log -> 2",
+    "logb" => "8 2 logb -> 3",
+    "sqrt" => "16 sqrt -> 4",
+    "!" => "5 ! -> 120",
+    "C" => "5 2 C -> 10",
+    "factratio" => "6 4 factratio -> 30",
+    "digitalroot" => "12345 digitalroot -> 6",
+    "absdiff" => "5 8 absdiff -> 3",
+    "<>" => "1 2 <> -> 2 1",
+    "rot" => "1 2 3 rot -> 2 3 1",
+    "sort" => "3 1 2 sort -> 1 2 3",
+    "range" => "1 5 2 range -> 1 3 5",
+    "map" => "1 4 9 \"sqrt\" map -> 1 2 3",
+    "zipwith" => "10 20 1 2 2 \"-\" zipwith -> 9 18",
+};
+
+/// The worked example for an operator, if it has one (e.g. `3 4 + -> 7`).
+pub fn help_example(token: &str) -> Option<&'static str> {
+    HELP_EXAMPLES.get(token).copied()
+}
+
+/// Help for one operator as printed by `"token" help`: its group, its usage
+/// and, when there is one, a worked example.
+pub fn help_details(token: &str, mode: AngleMode) -> Option<String> {
+    let (group, _usage, _action) = OPERATOR_DATA.get(token)?;
+    let mut details = format!("  Type: {}\n  Usage: {}", group, help_text(token, mode)?);
+    if let Some(example) = help_example(token) {
+        details.push_str(&format!("\n  Example: {}", example));
+    }
+    Some(details)
+}
+
 /// Displays help for all functions or a specific function, reading from the centralized map.
-fn display_help(token: &str) -> Result<(), &'static str> {
+fn display_help(token: &str, mode: AngleMode) -> Result<(), &'static str> {
     if token.is_empty() {
         // List all available functions, grouped by type
         println!("\n--- Available Functions ---");
 
         // Use a standard HashMap for runtime grouping
-        let mut grouped_help: HashMap<&'static str, Vec<(&'static str, &'static str)>> =
-            HashMap::new();
+        let mut grouped_help: HashMap<&'static str, Vec<&'static str>> = HashMap::new();
         // Iterate over the centralized map to extract help data only
-        for (func, (group, usage, _action)) in OPERATOR_DATA.entries() {
-            grouped_help.entry(group).or_default().push((*func, *usage));
+        for (func, (group, _usage, _action)) in OPERATOR_DATA.entries() {
+            grouped_help.entry(group).or_default().push(*func);
         }
 
         let groups = vec![
@@ -98,27 +860,39 @@ fn display_help(token: &str) -> Result<(), &'static str> {
             "Rounding",
             "Conversions",
             "Combinatorics",
-            "Constants",
+            "NumberTheory",
+            "Constant",
+            "Physics",
+            "Complex",
+            "Calendar",
+            "Statistics",
+            "LinearAlgebra",
+            "Comparison",
+            "Sequences",
+            "Functional",
             "Stack",
             "Memory",
+            "Programming",
             "Display",
+            "Validation",
+            "IO",
             "Meta",
         ];
 
         for group in groups {
             if let Some(items) = grouped_help.get(group) {
                 println!("\n  ✨ {}:", group);
-                for (func, usage) in items {
+                for func in items {
+                    let usage = help_text(func, mode).unwrap_or_default();
                     println!("    - {:<5} | {}", func, usage);
                 }
             }
         }
     } else {
         // Show help for a specific function
-        if let Some((group, usage, _action)) = OPERATOR_DATA.get(token) {
+        if let Some(details) = help_details(token, mode) {
             println!("\n--- Help for '{}' ---", token);
-            println!("  Type: {}", group);
-            println!("  Usage: {}", usage);
+            println!("{}", details);
         } else {
             return Err("Function not found. Type 'help' for a full list.");
         }
@@ -127,90 +901,572 @@ fn display_help(token: &str) -> Result<(), &'static str> {
     Ok(())
 }
 
-/// Stores a number value from the stack into storage, identified by a key from the stack.
-///
-/// Expected stack order: [..., value, key]
-/// Mutates the stack by popping two items. Mutates storage by inserting the key-value pair.
-pub fn store(
+/// Pops `actual` (top) and `expected` and succeeds if they are equal within a
+/// small tolerance. On failure, the operands are restored and `detail` is set to
+/// "assertion failed: expected X, got Y".
+pub fn assert_equal(
     stack: &mut Vec<StackItem>,
-    storage: &mut HashMap<String, f64>,
+    detail: &mut Option<String>,
 ) -> Result<(), &'static str> {
-    // 1. Pop the key (must be a Key variant)
-    let key = match stack.pop() {
-        Some(StackItem::Key(k)) => k.to_string(), // Convert Box<str> to String for HashMap key
-        // If the item wasn't a Key, return an error.
-        item => {
-            // If we popped something but it was the wrong type, push it back.
-            if let Some(i) = item {
-                stack.push(i);
-            }
-            return Err("STO requires a string key (e.g., \"rate\") as the last item");
-        }
+    let [expected, actual] = match pop_numbers(stack) {
+        Some(values) => values,
+        None => return Err("assert requires two numbers (expected, actual) on the stack"),
     };
 
-    // 2. Pop the value (must be a Number variant)
-    let val = match stack.pop() {
-        Some(StackItem::Number(v)) => v,
-        _ => {
-            // Crucial: The key was already popped. If value pop fails,
-            // we have an inconsistent state. A simple error is sufficient here.
-            return Err("STO requires a number value before the key");
+    let tolerance = 1e-9 * expected.abs().max(1.0);
+    if (expected - actual).abs() <= tolerance || expected == actual {
+        return Ok(());
+    }
+
+    *detail = Some(format!(
+        "assertion failed: expected {}, got {}",
+        expected, actual
+    ));
+    stack.push(StackItem::Number(expected));
+    stack.push(StackItem::Number(actual));
+    Err("assertion failed: expected and actual values differ")
+}
+
+/// Pops a number and uses it to reseed the calculator's random generator.
+/// The seed must be a non-negative integer of at most 2^53, so distinct seeds
+/// always give distinct sequences.
+pub fn seed(calc: &mut Calculator) -> Result<(), &'static str> {
+    let [val] = pop_numbers(&mut calc.stack).ok_or("seed requires one number on the stack")?;
+    if !(0.0..=MAX_EXACT_INT).contains(&val) || val.fract() != 0.0 {
+        calc.stack.push(StackItem::Number(val));
+        return Err("seed requires a non-negative integer of at most 2^53");
+    }
+    calc.seed(val as u64);
+    Ok(())
+}
+
+/// Maximum number of iterations for a single `dowhile` loop.
+const MAX_LOOP_ITERATIONS: usize = 1_000_000;
+
+/// Pops two keys and returns them as (below, top). Returns None, leaving the
+/// stack untouched, unless the top two items are both keys.
+fn pop_two_keys(stack: &mut Vec<StackItem>) -> Option<(String, String)> {
+    match stack.as_mut_slice() {
+        [.., StackItem::Key(below), StackItem::Key(top)] => {
+            let keys = (std::mem::take(below), std::mem::take(top));
+            stack.truncate(stack.len() - 2);
+            Some(keys)
         }
+        _ => None,
+    }
+}
+
+/// Defines a macro: a name that runs a stored line of tokens when used.
+///
+/// Expected stack order: [..., "body", "name"]
+pub fn define(calc: &mut Calculator) -> Result<(), &'static str> {
+    let (body, name) = pop_two_keys(&mut calc.stack)
+        .ok_or("def requires two keys (\"body\" \"name\") on the stack")?;
+
+    let err = if body.trim().is_empty() {
+        Some("def: macro body cannot be empty")
+    } else if OPERATOR_DATA.contains_key(name.as_str()) {
+        // Operators are looked up first, so such a macro could never run.
+        Some("def: name shadows a built-in operator")
+    } else {
+        None
     };
+    if let Some(err) = err {
+        calc.stack.push(StackItem::Key(body));
+        calc.stack.push(StackItem::Key(name));
+        return Err(err);
+    }
 
-    storage.insert(key, val);
+    calc.macros.insert(name, body);
     Ok(())
 }
 
-/// Recalls a number value from storage onto the stack, identified by a key from the stack.
+/// Makes `name` a shortcut for an existing operator or macro.
+/// Expected stack order: [..., "name", "target"]
+pub fn alias(calc: &mut Calculator) -> Result<(), &'static str> {
+    let (name, target) = pop_two_keys(&mut calc.stack)
+        .ok_or("alias requires two keys (\"name\" \"target\") on the stack")?;
+
+    let err = if OPERATOR_DATA.contains_key(name.as_str()) {
+        Some("alias: name shadows a built-in operator")
+    } else if name == target
+        || !(OPERATOR_DATA.contains_key(target.as_str()) || calc.macros.contains_key(&target))
+    {
+        Some("alias: target must be an existing operator or macro")
+    } else {
+        None
+    };
+    if let Some(err) = err {
+        calc.stack.push(StackItem::Key(name));
+        calc.stack.push(StackItem::Key(target));
+        return Err(err);
+    }
+
+    calc.aliases.insert(name, target);
+    Ok(())
+}
+
+/// Runs the `body` macro, then evaluates the `cond` macro on a scratch copy of
+/// the stack; loops while the condition leaves a nonzero number on top.
+/// The body always runs at least once. Only the stack is scratch for the
+/// condition: storage changes made by it persist.
+/// If any iteration fails, the stack and both keys are restored to how they
+/// were before the loop (storage changes are kept).
 ///
-/// Expected stack order: [..., key]
-/// Mutates the stack by popping the key and pushing the retrieved number.
-pub fn recall(
-    stack: &mut Vec<StackItem>,
-    storage: &HashMap<String, f64>,
-) -> Result<(), &'static str> {
-    // 1. Pop the key
-    let key = match stack.pop() {
-        Some(StackItem::Key(k)) => k.to_string(),
-        item => {
-            if let Some(i) = item {
-                stack.push(i);
-            }
-            return Err("RCL requires a string key (e.g., \"rate\") as the last item");
+/// Expected stack order: [..., "body", "cond"]
+pub fn do_while(calc: &mut Calculator) -> Result<(), &'static str> {
+    let (body_name, cond_name) = pop_two_keys(&mut calc.stack)
+        .ok_or("dowhile requires two keys (\"body\" \"cond\") on the stack")?;
+    let (body, cond) = match (calc.macros.get(&body_name), calc.macros.get(&cond_name)) {
+        (Some(body), Some(cond)) => (body.clone(), cond.clone()),
+        _ => {
+            calc.stack.push(StackItem::Key(body_name));
+            calc.stack.push(StackItem::Key(cond_name));
+            return Err("dowhile: body and condition must be defined macros");
         }
     };
 
-    // 2. Look up the value and push it back
-    if let Some(&val) = storage.get(&key) {
-        stack.push(StackItem::Number(val));
-        Ok(())
-    } else {
-        // If the key wasn't found, push the key back onto the stack
-        // (as the user might try a different key)
-        stack.push(StackItem::Key(key.into()));
-        Err("Storage key not found")
+    let original = calc.stack.clone();
+    let outcome = run_do_while(calc, &body, &cond);
+    if outcome.is_err() {
+        calc.stack = original;
+        calc.stack.push(StackItem::Key(body_name));
+        calc.stack.push(StackItem::Key(cond_name));
     }
+    outcome
 }
 
-/// Calculates the factorial of n (n!).
-/// Returns an error if n is negative, non-integer, or too large.
+/// The `dowhile` loop itself; may leave the stack partly changed on error.
+fn run_do_while(calc: &mut Calculator, body: &str, cond: &str) -> Result<(), &'static str> {
+    for _ in 0..MAX_LOOP_ITERATIONS {
+        calc.run_macro(body)?;
+
+        let saved = calc.stack.clone();
+        let result = calc.run_macro(cond);
+        let verdict = match calc.stack.last() {
+            Some(StackItem::Number(val)) => Some(*val != 0.0),
+            _ => None,
+        };
+        calc.stack = saved;
+        result?;
+
+        match verdict {
+            Some(true) => continue,
+            Some(false) => return Ok(()),
+            None => return Err("dowhile: condition must leave a number on the stack"),
+        }
+    }
+
+    Err("dowhile: iteration limit (1,000,000) exceeded")
+}
+
+/// Applies a unary operator or macro to each number in the top run, in place.
+/// Each number is processed on its own one-item scratch stack, which must end up
+/// holding exactly one number. Items below the run are left untouched.
+///
+/// Expected stack order: [..., x1, .., xn, "name"]
+pub fn map(calc: &mut Calculator) -> Result<(), &'static str> {
+    let name = match calc.stack.pop() {
+        Some(StackItem::Key(name)) => name,
+        other => {
+            calc.stack.extend(other);
+            return Err("map requires a key naming a unary operator or macro on top of the stack");
+        }
+    };
+
+    let target = calc.aliases.get(&name).unwrap_or(&name);
+    let is_unary = match OPERATOR_DATA.get(target.as_str()) {
+        Some((_, _, action)) => matches!(action, OperatorAction::Unary(_)),
+        None => calc.macros.contains_key(target),
+    };
+    let err = if !is_unary {
+        Some("map: key must name a unary operator or macro")
+    } else if stats::numeric_run_len(&calc.stack) == 0 {
+        Some("map requires numbers below the key")
+    } else {
+        None
+    };
+    if let Some(err) = err {
+        calc.stack.push(StackItem::Key(name));
+        return Err(err);
+    }
+
+    let values = stats::take_numeric_run(&mut calc.stack);
+    let below = std::mem::take(&mut calc.stack);
+    let outcome: Result<Vec<f64>, &'static str> = values
+        .iter()
+        .map(|val| {
+            calc.stack = vec![StackItem::Number(*val)];
+            calc.process_token(&name)?;
+            match calc.stack.as_slice() {
+                [StackItem::Number(result)] => Ok(*result),
+                _ => Err("map: operator must turn one number into one number"),
+            }
+        })
+        .collect();
+
+    calc.stack = below;
+    match outcome {
+        Ok(results) => {
+            stats::push_numbers(&mut calc.stack, &results);
+            Ok(())
+        }
+        Err(e) => {
+            stats::push_numbers(&mut calc.stack, &values);
+            calc.stack.push(StackItem::Key(name));
+            Err(e)
+        }
+    }
+}
+
+/// Pops a name key and saves the stack, storage and last answer under it,
+/// replacing any checkpoint of the same name.
+/// Expected stack order: [..., "name"]
+pub fn checkpoint(calc: &mut Calculator) -> Result<(), &'static str> {
+    let name = match calc.stack.pop() {
+        Some(StackItem::Key(name)) => name,
+        item => {
+            restore_operands(&mut calc.stack, item.as_slice());
+            return Err("checkpoint requires a name key on the stack");
+        }
+    };
+    let state = CalculatorState {
+        stack: calc.stack.clone(),
+        storage: calc.storage.clone(),
+        last_answer: calc.last_answer,
+    };
+    calc.checkpoints.insert(name, state);
+    Ok(())
+}
+
+/// Pops a name key and replaces the stack, storage and last answer with the
+/// checkpoint saved under it. The checkpoint is kept, so it can be restored again.
+/// When run from a line, the end-of-line answer update still applies afterwards.
+/// Expected stack order: [..., "name"]
+pub fn restore(calc: &mut Calculator) -> Result<(), &'static str> {
+    let state = match calc.stack.last() {
+        Some(StackItem::Key(name)) => calc
+            .checkpoints
+            .get(name)
+            .ok_or("restore: no checkpoint with that name")?,
+        _ => return Err("restore requires a name key on the stack"),
+    };
+    calc.stack = state.stack.clone();
+    calc.storage = state.storage.clone();
+    calc.last_answer = state.last_answer;
+    Ok(())
+}
+
+/// Combines two blocks of n numbers pairwise with a binary operator. The
+/// deeper block gives the left operands: a1 .. an b1 .. bn n "op" zipwith
+/// pushes (a1 op b1) .. (an op bn).
+///
+/// Example: [10, 20, 1, 2] 2 "-" zipwith -> [9, 18]
+pub fn zip_with(calc: &mut Calculator) -> Result<(), &'static str> {
+    let (n, name) = match calc.stack.as_slice() {
+        [.., StackItem::Number(n), StackItem::Key(name)] => (*n, name.clone()),
+        _ => return Err("zipwith requires a count and then a key naming a binary operator"),
+    };
+    let target = calc.aliases.get(&name).unwrap_or(&name);
+    if !matches!(
+        OPERATOR_DATA.get(target.as_str()),
+        Some((_, _, OperatorAction::Binary(_)))
+    ) {
+        return Err("zipwith: key must name a binary operator");
+    }
+    let depth = calc.stack.len() - 2;
+    // Compare in f64 so a huge count cannot overflow the usize arithmetic
+    if n < 1.0 || n.fract() != 0.0 || 2.0 * n > stats::numeric_run_len(&calc.stack[..depth]) as f64
+    {
+        return Err("zipwith requires a whole count n >= 1 and 2n numbers below it");
+    }
+
+    calc.stack.truncate(depth);
+    let n = n as usize;
+    let values = stats::take_numeric_run(&mut calc.stack);
+    let (rest, pairs) = values.split_at(values.len() - 2 * n);
+    let (lefts, rights) = pairs.split_at(n);
+    let below = std::mem::take(&mut calc.stack);
+
+    let outcome: Result<Vec<f64>, &'static str> = lefts
+        .iter()
+        .zip(rights)
+        .map(|(a, b)| {
+            calc.stack = vec![StackItem::Number(*a), StackItem::Number(*b)];
+            calc.process_token(&name)?;
+            match calc.stack.as_slice() {
+                [StackItem::Number(result)] => Ok(*result),
+                _ => Err("zipwith: operator must turn two numbers into one number"),
+            }
+        })
+        .collect();
+
+    calc.stack = below;
+    stats::push_numbers(&mut calc.stack, rest);
+    match outcome {
+        Ok(results) => {
+            stats::push_numbers(&mut calc.stack, &results);
+            Ok(())
+        }
+        Err(e) => {
+            stats::push_numbers(&mut calc.stack, pairs);
+            calc.stack.push(StackItem::Number(n as f64));
+            calc.stack.push(StackItem::Key(name));
+            Err(e)
+        }
+    }
+}
+
+/// Exchanges the top number with the last answer register.
+/// The old answer replaces the top of the stack, and the old top becomes the answer.
+///
+/// Note: the calculator pins the new answer, so the end-of-line answer update
+/// leaves it in place on this and later lines until `a` recalls it.
+pub fn swap_with_answer(
+    stack: &mut [StackItem],
+    last_answer: &mut Option<f64>,
+) -> Result<(), &'static str> {
+    let top = match stack.last_mut() {
+        Some(StackItem::Number(val)) => val,
+        _ => return Err("x<>a requires one number on the stack"),
+    };
+    let answer = last_answer
+        .as_mut()
+        .ok_or("No previous answer available ('a' is empty)")?;
+
+    std::mem::swap(top, answer);
+    Ok(())
+}
+
+/// Stores a number value from the stack into storage, identified by a key from the stack.
+///
+/// Expected stack order: [..., value, key]
+/// Mutates the stack by popping two items. Mutates storage by inserting the key-value pair.
+pub fn store(
+    stack: &mut Vec<StackItem>,
+    storage: &mut HashMap<String, f64>,
+) -> Result<(), &'static str> {
+    // 1. Pop the key (must be a Key variant)
+    let key = match stack.pop() {
+        Some(StackItem::Key(k)) => k.to_string(), // Convert Box<str> to String for HashMap key
+        // If the item wasn't a Key, return an error.
+        item => {
+            // If we popped something but it was the wrong type, push it back.
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            return Err("STO requires a string key (e.g., \"rate\") as the last item");
+        }
+    };
+
+    // 2. Pop the value (must be a Number variant)
+    let val = match stack.pop() {
+        Some(StackItem::Number(v)) => v,
+        _ => {
+            // Crucial: The key was already popped. If value pop fails,
+            // we have an inconsistent state. A simple error is sufficient here.
+            return Err("STO requires a number value before the key");
+        }
+    };
+
+    storage.insert(key, val);
+    Ok(())
+}
+
+/// Exchanges the number below the key with the value stored under the key:
+/// the new value is stored and the old stored value is pushed in its place.
+/// Nothing changes unless the key already exists.
+/// Expected stack order: [..., value, "key"]
+pub fn store_swap(
+    stack: &mut Vec<StackItem>,
+    storage: &mut HashMap<String, f64>,
+) -> Result<(), &'static str> {
+    let (val, key) = match stack.as_slice() {
+        [.., StackItem::Number(val), StackItem::Key(key)] => (*val, key),
+        _ => return Err("sto<> requires a number and then a string key (e.g., 5 \"rate\")"),
+    };
+    let Some(stored) = storage.get_mut(key) else {
+        return Err("Storage key not found");
+    };
+
+    let old = std::mem::replace(stored, val);
+    stack.truncate(stack.len() - 2);
+    stack.push(StackItem::Number(old));
+    Ok(())
+}
+
+/// Stores value under key only if cond is nonzero. All three operands are
+/// consumed either way, so the stack looks the same whether or not it stored.
+/// Expected stack order: [..., value, cond, "key"]
+pub fn store_if(
+    stack: &mut Vec<StackItem>,
+    storage: &mut HashMap<String, f64>,
+) -> Result<(), &'static str> {
+    let (val, cond, key) = match stack.as_slice() {
+        [
+            ..,
+            StackItem::Number(val),
+            StackItem::Number(cond),
+            StackItem::Key(key),
+        ] => (*val, *cond, key.clone()),
+        _ => {
+            return Err(
+                "sto? requires a value, a condition and then a string key (e.g., 42 1 \"x\")",
+            );
+        }
+    };
+
+    stack.truncate(stack.len() - 3);
+    if cond != 0.0 {
+        storage.insert(key, val);
+    }
+    Ok(())
+}
+
+/// Recalls a number value from storage onto the stack, identified by a key from the stack.
+///
+/// Expected stack order: [..., key]
+/// Mutates the stack by popping the key and pushing the retrieved number.
+pub fn recall(
+    stack: &mut Vec<StackItem>,
+    storage: &HashMap<String, f64>,
+) -> Result<(), &'static str> {
+    // 1. Pop the key
+    let key = match stack.pop() {
+        Some(StackItem::Key(k)) => k.to_string(),
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            return Err("RCL requires a string key (e.g., \"rate\") as the last item");
+        }
+    };
+
+    // 2. Look up the value and push it back
+    if let Some(&val) = storage.get(&key) {
+        stack.push(StackItem::Number(val));
+        Ok(())
+    } else {
+        // If the key wasn't found, push the key back onto the stack
+        // (as the user might try a different key)
+        stack.push(StackItem::Key(key));
+        Err("Storage key not found")
+    }
+}
+
+/// Saves a copy of the stack under a name, identified by a key from the stack.
+///
+/// Expected stack order: [..., numbers, "name"]
+/// Only the key is popped; every remaining item must be a number.
+pub fn save_stack(
+    stack: &mut Vec<StackItem>,
+    stack_storage: &mut HashMap<String, Vec<f64>>,
+) -> Result<(), &'static str> {
+    let name = match stack.last() {
+        Some(StackItem::Key(k)) => k.clone(),
+        _ => return Err("savestack requires a string key (e.g., \"a\") as the last item"),
+    };
+
+    let values: Option<Vec<f64>> = stack[..stack.len() - 1]
+        .iter()
+        .map(|item| match item {
+            StackItem::Number(val) => Some(*val),
+            _ => None,
+        })
+        .collect();
+    let values = values.ok_or("savestack requires every item below the key to be a number")?;
+
+    stack.pop();
+    stack_storage.insert(name, values);
+    Ok(())
+}
+
+/// Moves the top n numbers into a list under a name, so `unpack` can push
+/// them back in the same order.
+///
+/// Expected stack order: [..., x1, .., xn, n, "name"]
+pub fn pack(
+    stack: &mut Vec<StackItem>,
+    list_storage: &mut HashMap<String, Vec<f64>>,
+) -> Result<(), &'static str> {
+    let (n, name) = match stack.as_slice() {
+        [.., StackItem::Number(n), StackItem::Key(name)] => (*n, name.clone()),
+        _ => return Err("pack requires a count and then a string key (e.g., 3 \"grp\")"),
+    };
+    let depth = stack.len() - 2;
+    if n < 0.0 || n.fract() != 0.0 || n as usize > stats::numeric_run_len(&stack[..depth]) {
+        return Err("pack: count must be a whole number no larger than the numbers below it");
+    }
+
+    stack.truncate(depth);
+    let values = stack
+        .drain(depth - n as usize..)
+        .map(|item| match item {
+            StackItem::Number(val) => val,
+            _ => unreachable!("numeric run only contains numbers"),
+        })
+        .collect();
+    list_storage.insert(name, values);
+    Ok(())
+}
+
+/// Pushes the list packed under a name, identified by a key from the stack.
+///
+/// Expected stack order: [..., "name"]
+pub fn unpack(
+    stack: &mut Vec<StackItem>,
+    list_storage: &HashMap<String, Vec<f64>>,
+) -> Result<(), &'static str> {
+    let values = match stack.last() {
+        Some(StackItem::Key(k)) => list_storage
+            .get(k)
+            .ok_or("unpack: no list packed under that name")?,
+        _ => return Err("unpack requires a string key (e.g., \"grp\") as the last item"),
+    };
+
+    stack.pop();
+    stack.extend(values.iter().map(|val| StackItem::Number(*val)));
+    Ok(())
+}
+
+/// Pushes the stack saved under a name, identified by a key from the stack.
+///
+/// Expected stack order: [..., "name"]
+pub fn load_stack(
+    stack: &mut Vec<StackItem>,
+    stack_storage: &HashMap<String, Vec<f64>>,
+) -> Result<(), &'static str> {
+    let values = match stack.last() {
+        Some(StackItem::Key(k)) => stack_storage.get(k).ok_or("Saved stack not found")?,
+        _ => return Err("loadstack requires a string key (e.g., \"a\") as the last item"),
+    };
+
+    stack.pop();
+    stack.extend(values.iter().map(|val| StackItem::Number(*val)));
+    Ok(())
+}
+
+/// Calculates the factorial of n (n!).
+/// Returns an error if n is negative, non-integer, or too large.
 pub fn factorial(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
     // 1. Pop the number
     let val = match stack.pop() {
         Some(StackItem::Number(val)) => val,
-        _ => return Err("Factorial '!' requires one number on the stack"),
+        item => {
+            restore_operands(stack, item.as_slice());
+            return Err("Factorial '!' requires one number on the stack");
+        }
     };
 
     // 2. Check for negative input
     if val < 0.0 {
-        stack.push(StackItem::Number(val));
+        restore_operands(stack, &[StackItem::Number(val)]);
         return Err("Factorial '!' requires a non-negative number.");
     }
 
     // 3. Check for large input (21! is already too large for f64)
     if val > 20.0 {
-        stack.push(StackItem::Number(val));
+        restore_operands(stack, &[StackItem::Number(val)]);
         return Err("Factorial '!' is too large; max supported value is 20.");
     }
 
@@ -227,55 +1483,42 @@ pub fn factorial(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
 /// Pops two numbers (k, n), calculates P(n, k), and pushes the result.
 pub fn permutations(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
     // RPN: requires n then k, so pop k first, then n.
-    // Use an error handler helper to avoid repeating pop/push logic on failure
-    let handle_error = |stack: &mut Vec<StackItem>, n_val, k_val, err| {
-        // Push back in reverse order (k then n) since they are currently popped
-        stack.push(StackItem::Number(n_val));
-        stack.push(StackItem::Number(k_val));
-        Err(err)
-    };
-
     let k_val = match stack.pop() {
         Some(StackItem::Number(val)) => val,
-        _ => return Err("P(n, k) requires two numbers (n, k) on the stack (missing k)"),
+        item => {
+            restore_operands(stack, item.as_slice());
+            return Err("P(n, k) requires two numbers (n, k) on the stack (missing k)");
+        }
     };
     let n_val = match stack.pop() {
         Some(StackItem::Number(val)) => val,
-        _ => {
-            return handle_error(
-                stack,
-                0.0,
-                k_val,
-                "P(n, k) requires two numbers (n, k) on the stack (missing n)",
-            );
+        item => {
+            restore_operands(stack, item.as_slice());
+            restore_operands(stack, &[StackItem::Number(k_val)]);
+            return Err("P(n, k) requires two numbers (n, k) on the stack (missing n)");
         }
     };
+    // On failure, both operands are restored in their original order (n then k).
+    let operands = [StackItem::Number(n_val), StackItem::Number(k_val)];
 
     // Check bounds and non-negativity
     if n_val < 0.0 || k_val < 0.0 {
-        return handle_error(stack, n_val, k_val, "P(n, k) requires non-negative inputs.");
+        restore_operands(stack, &operands);
+        return Err("P(n, k) requires non-negative inputs.");
     }
 
     let n = n_val.round() as i64;
     let k = k_val.round() as i64;
 
     if k > n {
-        return handle_error(
-            stack,
-            n_val,
-            k_val,
-            "P(n, k): n must be greater than or equal to k.",
-        );
+        restore_operands(stack, &operands);
+        return Err("P(n, k): n must be greater than or equal to k.");
     }
 
     // Check for large input to avoid overflow in intermediate calculation (max 20!)
     if n > 20 || k > 20 {
-        return handle_error(
-            stack,
-            n_val,
-            k_val,
-            "P(n, k): Inputs too large; max n is 20.",
-        );
+        restore_operands(stack, &operands);
+        return Err("P(n, k): Inputs too large; max n is 20.");
     }
 
     // P(n, k) = n * (n-1) * ... * (n-k+1)
@@ -289,55 +1532,43 @@ pub fn permutations(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
 /// Pops two numbers (k, n), calculates C(n, k), and pushes the result.
 pub fn combinations(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
     // RPN: requires n then k, so pop k first, then n.
-    let handle_error = |stack: &mut Vec<StackItem>, n_val, k_val, err| {
-        // Push back in reverse order (k then n) since they are currently popped
-        stack.push(StackItem::Number(n_val));
-        stack.push(StackItem::Number(k_val));
-        Err(err)
-    };
-
     let k_val = match stack.pop() {
         Some(StackItem::Number(val)) => val,
-        _ => return Err("C(n, k) requires two numbers (n, k) on the stack (missing k)"),
+        item => {
+            restore_operands(stack, item.as_slice());
+            return Err("C(n, k) requires two numbers (n, k) on the stack (missing k)");
+        }
     };
     let n_val = match stack.pop() {
         Some(StackItem::Number(val)) => val,
-        _ => {
-            return handle_error(
-                stack,
-                0.0,
-                k_val,
-                "C(n, k) requires two numbers (n, k) on the stack (missing n)",
-            );
+        item => {
+            restore_operands(stack, item.as_slice());
+            restore_operands(stack, &[StackItem::Number(k_val)]);
+            return Err("C(n, k) requires two numbers (n, k) on the stack (missing n)");
         }
     };
+    // On failure, both operands are restored in their original order (n then k).
+    let operands = [StackItem::Number(n_val), StackItem::Number(k_val)];
 
     // Check bounds and non-negativity
     if n_val < 0.0 || k_val < 0.0 {
-        return handle_error(stack, n_val, k_val, "C(n, k) requires non-negative inputs.");
+        restore_operands(stack, &operands);
+        return Err("C(n, k) requires non-negative inputs.");
     }
 
     let n = n_val.round() as i64;
     let k = k_val.round() as i64;
 
     if k > n {
-        return handle_error(
-            stack,
-            n_val,
-            k_val,
-            "C(n, k): n must be greater than or equal to k.",
-        );
+        restore_operands(stack, &operands);
+        return Err("C(n, k): n must be greater than or equal to k.");
     }
 
     // Check for large input (C(n, k) can exceed f64, e.g., C(67, 33))
     // A safe upper limit for n, considering the final f64 result is ~10^308
     if n > 170 {
-        return handle_error(
-            stack,
-            n_val,
-            k_val,
-            "C(n, k): n is too large (> 170) for f64 result.",
-        );
+        restore_operands(stack, &operands);
+        return Err("C(n, k): n is too large (> 170) for f64 result.");
     }
 
     // Optimization: C(n, k) = C(n, n-k)
@@ -354,8 +1585,40 @@ pub fn combinations(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
     Ok(())
 }
 
-/// Swaps the position of the last two number values on the stack.
-pub fn swap(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+/// n!/m! as the product (m+1) * (m+2) * ... * n, so ratios like 100!/98!
+/// work even though both factorials overflow.
+/// Expected stack order: [..., n, m]
+pub fn factorial_ratio(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n, m] = pop_numbers(stack).ok_or("factratio requires two numbers (n m) on the stack")?;
+    let is_count = |v: f64| (0.0..=MAX_EXACT_INT).contains(&v) && v.fract() == 0.0;
+    let result = if !is_count(n) || !is_count(m) {
+        Err("factratio requires non-negative integers of at most 2^53")
+    } else if n < m {
+        Err("factratio: n must be greater than or equal to m")
+    } else {
+        // Factors grow with i, so overflow (and the early exit) comes within a few
+        // hundred steps however large n is.
+        (m as u64 + 1..=n as u64)
+            .try_fold(1.0, |product: f64, i| {
+                Some(product * i as f64).filter(|p| p.is_finite())
+            })
+            .ok_or("factratio: result is too large")
+    };
+    match result {
+        Ok(product) => {
+            stack.push(StackItem::Number(product));
+            Ok(())
+        }
+        Err(err) => {
+            restore_operands(stack, &[n, m].map(StackItem::Number));
+            Err(err)
+        }
+    }
+}
+
+/// Swaps the position of the last two items on the stack.
+/// Works for any combination of numbers and keys.
+pub fn swap(stack: &mut [StackItem]) -> Result<(), &'static str> {
     if stack.len() < 2 {
         return Err("Not enough items on the stack to swap");
     }
@@ -369,3 +1632,719 @@ pub fn swap(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
 
     Ok(())
 }
+
+/// Rotates the top three items (numbers or keys), bringing the third to the top.
+/// Example: [1, 2, 3] rot -> [2, 3, 1]
+pub fn rot(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    let len = stack.len();
+    if len < 3 {
+        return Err("rot requires three items on the stack");
+    }
+    stack[len - 3..].rotate_left(1);
+    Ok(())
+}
+
+/// Inverse of `rot`: moves the top item down to the third position.
+/// Example: [1, 2, 3] rot- -> [3, 1, 2]
+pub fn rot_back(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    let len = stack.len();
+    if len < 3 {
+        return Err("rot- requires three items on the stack");
+    }
+    stack[len - 3..].rotate_right(1);
+    Ok(())
+}
+
+/// Moves the top item to the bottom of the stack, keeping the rest in order.
+/// Example: [1, 2, 3] sink -> [3, 1, 2]
+pub fn sink(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    if stack.is_empty() {
+        return Err("sink requires an item on the stack");
+    }
+    stack.rotate_right(1);
+    Ok(())
+}
+
+/// Inverse of `sink`: moves the bottom item to the top of the stack.
+/// Example: [1, 2, 3] float -> [2, 3, 1]
+pub fn float(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    if stack.is_empty() {
+        return Err("float requires an item on the stack");
+    }
+    stack.rotate_left(1);
+    Ok(())
+}
+
+/// Pops a level n and moves the item below it down to level n (1 = top),
+/// shifting the items in between up by one. 0 and 1 leave the stack unchanged.
+///
+/// Example: [1, 2, 3, 4] 2 bury -> [1, 2, 4, 3]
+pub fn bury(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n] = pop_numbers(stack).ok_or("bury requires a level n on top of the stack")?;
+
+    if n < 0.0 || n.fract() != 0.0 {
+        stack.push(StackItem::Number(n));
+        return Err("bury: n must be a non-negative integer");
+    }
+    if n > stack.len() as f64 {
+        stack.push(StackItem::Number(n));
+        return Err("bury: n is deeper than the stack");
+    }
+
+    let level = n as usize;
+    if level > 1 {
+        let len = stack.len();
+        stack[len - level..].rotate_right(1);
+    }
+    Ok(())
+}
+
+/// Rounds x to n significant figures. Zero and non-finite values are returned unchanged.
+pub fn round_sig(x: f64, n: u32) -> f64 {
+    if x == 0.0 || !x.is_finite() {
+        return x;
+    }
+    let magnitude = x.abs().log10().floor() as i32;
+    let exponent = n as i32 - 1 - magnitude;
+    // Divide by an exact power of ten rather than multiplying by an inexact 10^-k.
+    if exponent >= 0 {
+        // For subnormal x, 10^exponent overflows, so scale in two steps there
+        let first = exponent.min(300);
+        let (scale, rest) = (10f64.powi(first), 10f64.powi(exponent - first));
+        (x * scale * rest).round() / rest / scale
+    } else {
+        let scale = 10f64.powi(-exponent);
+        (x / scale).round() * scale
+    }
+}
+
+/// Rounds x to n significant figures, n in 1..=15.
+/// Expected stack order: [..., x, n]
+pub fn sigfig(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [x, n] = pop_numbers(stack).ok_or("sigfig requires two numbers (x n) on the stack")?;
+    if !(1.0..=15.0).contains(&n) || n.fract() != 0.0 {
+        restore_operands(stack, &[StackItem::Number(x), StackItem::Number(n)]);
+        return Err("sigfig: n must be an integer from 1 to 15");
+    }
+    stack.push(StackItem::Number(round_sig(x, n as u32)));
+    Ok(())
+}
+
+/// Determinant of the 2x2 matrix [[a, b], [c, d]], entered row by row.
+/// Expected stack order: [..., a, b, c, d] -> a*d - b*c
+pub fn det2(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [a, b, c, d] = pop_numbers(stack)
+        .ok_or("det2 requires four numbers (a b c d, row by row) on the stack")?;
+    stack.push(StackItem::Number(a * d - b * c));
+    Ok(())
+}
+
+/// Percent error of a measurement: |actual - expected| / |expected| * 100.
+/// Expected stack order: [..., actual, expected]
+pub fn percent_error(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [actual, expected] =
+        pop_numbers(stack).ok_or("pcterr requires two numbers (actual expected) on the stack")?;
+    if expected == 0.0 {
+        restore_operands(stack, &[actual, expected].map(StackItem::Number));
+        return Err("pcterr: expected value cannot be zero");
+    }
+    stack.push(StackItem::Number(
+        (actual - expected).abs() / expected.abs() * 100.0,
+    ));
+    Ok(())
+}
+
+/// Pushes 1 if lo <= value <= hi (both ends inclusive), else 0.
+/// Expected stack order: [..., value, lo, hi]
+pub fn between(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [value, lo, hi] =
+        pop_numbers(stack).ok_or("between requires three numbers (value lo hi) on the stack")?;
+    if lo > hi {
+        restore_operands(stack, &[value, lo, hi].map(StackItem::Number));
+        return Err("between: lo must not be greater than hi");
+    }
+    let inside = lo <= value && value <= hi;
+    stack.push(StackItem::Number(if inside { 1.0 } else { 0.0 }));
+    Ok(())
+}
+
+/// Logarithm of a in base b, like `log` but with the inputs checked instead of
+/// giving NaN or infinity.
+/// Expected stack order: [..., a, b]
+pub fn log_base(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [a, b] = pop_numbers(stack).ok_or("logb requires two numbers (a b) on the stack")?;
+    let err = if b <= 0.0 {
+        Some("logb: base must be positive")
+    } else if b == 1.0 {
+        Some("logb: base cannot be 1")
+    } else if a <= 0.0 {
+        Some("logb: argument must be positive")
+    } else {
+        None
+    };
+    if let Some(err) = err {
+        restore_operands(stack, &[a, b].map(StackItem::Number));
+        return Err(err);
+    }
+    stack.push(StackItem::Number(crate::binary::log_op(a, b)));
+    Ok(())
+}
+
+/// Reduces an angle into [0, period). None for non-finite input.
+pub fn wrap_angle(angle: f64, period: f64) -> Option<f64> {
+    if !angle.is_finite() {
+        return None;
+    }
+    let wrapped = angle.rem_euclid(period);
+    // A tiny negative angle can round up to exactly one period.
+    Some(if wrapped >= period { 0.0 } else { wrapped })
+}
+
+/// Reduces the top number into one full turn of the current angle mode:
+/// [0, 360) for degrees, [0, 2pi) for radians.
+pub fn normalize_angle(stack: &mut Vec<StackItem>, mode: AngleMode) -> Result<(), &'static str> {
+    let [angle] = pop_numbers(stack).ok_or("normangle requires one number on the stack")?;
+    match wrap_angle(angle, mode.full_turn()) {
+        Some(wrapped) => {
+            stack.push(StackItem::Number(wrapped));
+            Ok(())
+        }
+        None => {
+            stack.push(StackItem::Number(angle));
+            Err("normangle requires a finite angle")
+        }
+    }
+}
+
+/// Fits a least-squares line to the numbers on top of the stack, read as
+/// interleaved pairs: x1 y1 x2 y2 ... xn yn. Pushes the slope, then the intercept.
+pub fn linfit(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    if !values.len().is_multiple_of(2) {
+        stats::push_numbers(stack, &values);
+        return Err("linfit requires x y pairs (an even count of numbers) on the stack");
+    }
+    let points: Vec<(f64, f64)> = values.chunks(2).map(|p| (p[0], p[1])).collect();
+    match stats::least_squares(&points) {
+        Some((slope, intercept)) => {
+            stats::push_numbers(stack, &[slope, intercept]);
+            Ok(())
+        }
+        None => {
+            stats::push_numbers(stack, &values);
+            Err("linfit requires at least two points with distinct x values")
+        }
+    }
+}
+
+/// Pops a block size n and swaps the top n items with the n items below them.
+/// Works on numbers and keys alike.
+///
+/// Example: [1, 2, 3, 4] 2 swapblock -> [3, 4, 1, 2]
+pub fn swap_block(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n] = pop_numbers(stack).ok_or("swapblock requires a block size n on top of the stack")?;
+
+    if n < 1.0 || n.fract() != 0.0 {
+        stack.push(StackItem::Number(n));
+        return Err("swapblock: n must be a positive integer");
+    }
+    // Compare in f64 so a huge size is neither saturated nor overflowed
+    if (stack.len() as f64) < 2.0 * n {
+        stack.push(StackItem::Number(n));
+        return Err("swapblock: needs at least 2n items below the block size");
+    }
+
+    let size = n as usize;
+    let len = stack.len();
+    stack[len - 2 * size..].rotate_left(size);
+    Ok(())
+}
+
+/// Pops a count n and discards everything except the top n items.
+/// An n larger than the stack keeps everything.
+pub fn keep(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n] = pop_numbers(stack).ok_or("keep requires a count n on top of the stack")?;
+
+    if n < 0.0 || n.fract() != 0.0 {
+        stack.push(StackItem::Number(n));
+        return Err("keep: n must be a non-negative integer");
+    }
+    let drop = stack.len().saturating_sub(n as usize);
+    stack.drain(..drop);
+    Ok(())
+}
+
+/// Replaces the numbers on top of the stack with their successive differences,
+/// later minus earlier: [10, 13, 11, 15] -> [3, -2, 4]. Items below the run are kept.
+pub fn differences(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    if values.len() < 2 {
+        stats::push_numbers(stack, &values);
+        return Err("diff requires at least two numbers on the stack");
+    }
+    let diffs: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+    stats::push_numbers(stack, &diffs);
+    Ok(())
+}
+
+/// Replaces the numbers on top of the stack with their running sums:
+/// [1, 2, 3, 4] -> [1, 3, 6, 10]. Items below the run are kept.
+/// An empty run is an error; a single number is left unchanged.
+pub fn cumulative_sum(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    if values.is_empty() {
+        return Err("cumsum requires at least one number on the stack");
+    }
+    let sums: Vec<f64> = values
+        .iter()
+        .scan(0.0, |total, val| {
+            *total += val;
+            Some(*total)
+        })
+        .collect();
+    stats::push_numbers(stack, &sums);
+    Ok(())
+}
+
+/// Real nth root of x. Exact when the root is an integer (e.g., 32 5 -> 2), and
+/// negative for negative x with odd n (e.g., -32 5 -> -2), unlike powf.
+/// Expected stack order: [..., x, n]
+pub fn exact_root(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [x, n] = pop_numbers(stack).ok_or("rootn requires two numbers (x n) on the stack")?;
+    let operands = [StackItem::Number(x), StackItem::Number(n)];
+
+    if n == 0.0 || n.fract() != 0.0 || n.abs() > i32::MAX as f64 {
+        restore_operands(stack, &operands);
+        return Err("rootn: n must be a non-zero integer");
+    }
+    let odd = n % 2.0 != 0.0;
+    if x < 0.0 && !odd {
+        restore_operands(stack, &operands);
+        return Err("rootn: even root of a negative number");
+    }
+
+    let root = x.abs().powf(1.0 / n);
+    // powf(1/n) can land just off an integer root (e.g., 4.999999999999999)
+    let rounded = root.round();
+    let root = if rounded.powi(n as i32) == x.abs() {
+        rounded
+    } else {
+        root
+    };
+    stack.push(StackItem::Number(if x < 0.0 { -root } else { root }));
+    Ok(())
+}
+
+/// Pops a file name key and writes the numeric stack to it as one comma-separated
+/// line, bottom to top. Values use Rust's shortest round-trip formatting, so no
+/// precision is lost. The stack must hold numbers only.
+pub fn export_csv(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let path = match stack.pop() {
+        Some(StackItem::Key(path)) => path,
+        item => {
+            restore_operands(stack, item.as_slice());
+            return Err("export requires a file name key on the stack");
+        }
+    };
+
+    let values: Option<Vec<String>> = stack
+        .iter()
+        .map(|item| match item {
+            StackItem::Number(val) => Some(val.to_string()),
+            _ => None,
+        })
+        .collect();
+    let Some(values) = values else {
+        stack.push(StackItem::Key(path));
+        return Err("export: the stack must contain only real numbers");
+    };
+
+    if let Err(e) = std::fs::write(&path, values.join(",") + "\n") {
+        eprintln!("export: could not write '{}': {}", path, e);
+        stack.push(StackItem::Key(path));
+        return Err("export: could not write the file");
+    }
+    Ok(())
+}
+
+/// Pops a file name key and runs every line of that file through `process_line`,
+/// on top of the current stack. Unlike the startup file, a script stops at the
+/// first failing line, which is named in `error_detail`; earlier lines keep their
+/// effect. Scripts may run other scripts, up to the macro nesting limit.
+pub fn run_file(calc: &mut Calculator) -> Result<(), &'static str> {
+    let path = match calc.stack.pop() {
+        Some(StackItem::Key(path)) => path,
+        item => {
+            restore_operands(&mut calc.stack, item.as_slice());
+            return Err("run requires a file name key on the stack");
+        }
+    };
+    if calc.macro_depth >= crate::MAX_MACRO_DEPTH {
+        calc.stack.push(StackItem::Key(path));
+        return Err("run: scripts nested too deep (script runs itself?)");
+    }
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => {
+            calc.error_detail = Some(format!("run: could not read '{}': {}", path, e));
+            calc.stack.push(StackItem::Key(path));
+            return Err("run: could not read the file");
+        }
+    };
+
+    calc.macro_depth += 1;
+    let result = contents.lines().enumerate().try_for_each(|(number, line)| {
+        calc.process_line(line).inspect_err(|e| {
+            let message = calc.error_detail.as_deref().unwrap_or(e);
+            calc.error_detail = Some(format!("run: {} line {}: {}", path, number + 1, message));
+        })
+    });
+    calc.macro_depth -= 1;
+    result
+}
+
+/// Checks a digit string against the Luhn checksum (credit cards, IMEIs, ...).
+/// Non-digit characters make the check fail.
+pub fn luhn_valid(digits: &str) -> bool {
+    if digits.is_empty() {
+        return false;
+    }
+    let mut sum = 0;
+    for (i, c) in digits.chars().rev().enumerate() {
+        let Some(d) = c.to_digit(10) else {
+            return false;
+        };
+        // Double every second digit from the right, subtracting 9 when it exceeds 9
+        sum += if i % 2 == 1 {
+            if d * 2 > 9 { d * 2 - 9 } else { d * 2 }
+        } else {
+            d
+        };
+    }
+    sum % 10 == 0
+}
+
+/// Replaces the top number with 1 if its digits pass the Luhn checksum, else 0.
+/// Only exact integers up to 2^53 keep every digit, so larger values are rejected.
+pub fn luhn(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n] = pop_numbers(stack).ok_or("luhn requires one number on the stack")?;
+    if !(0.0..=9_007_199_254_740_992.0).contains(&n) || n.fract() != 0.0 {
+        stack.push(StackItem::Number(n));
+        return Err("luhn requires a non-negative integer of at most 2^53");
+    }
+    let valid = luhn_valid(&format!("{:.0}", n));
+    stack.push(StackItem::Number(if valid { 1.0 } else { 0.0 }));
+    Ok(())
+}
+
+/// Removes repeated values from the numbers on top of the stack, keeping the
+/// first occurrence of each. Duplicates anywhere in the run count, not just
+/// adjacent ones, and values must be exactly equal (==). Items below the run are kept.
+///
+/// Example: [1, 2, 2, 3, 1] dedup -> [1, 2, 3]
+pub fn dedup(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    if values.is_empty() {
+        return Err("dedup requires at least one number on the stack");
+    }
+    let mut unique: Vec<f64> = Vec::with_capacity(values.len());
+    for val in values {
+        if !unique.contains(&val) {
+            unique.push(val);
+        }
+    }
+    stats::push_numbers(stack, &unique);
+    Ok(())
+}
+
+/// Sorts the numeric run at the top of the stack in ascending order. Uses
+/// `f64::total_cmp`, so the order is always the same: -inf < negatives < -0 <
+/// +0 < positives < +inf < NaN. Items below the run are kept.
+///
+/// Example: [3, NaN, 1, -inf] sort -> [-inf, 1, 3, NaN]
+pub fn sort(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let mut values = stats::take_numeric_run(stack);
+    if values.is_empty() {
+        return Err("sort requires at least one number on the stack");
+    }
+    values.sort_by(f64::total_cmp);
+    stats::push_numbers(stack, &values);
+    Ok(())
+}
+
+/// Rescales the numeric run at the top of the stack to [0, 1] with
+/// (x - min) / (max - min). If every value is the same, each becomes 0.
+/// Items below the run are kept.
+///
+/// Example: [0, 5, 10] minmaxnorm -> [0, 0.5, 1]
+pub fn min_max_normalize(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    let (Some(min), Some(max)) = (stats::minimum(&values), stats::maximum(&values)) else {
+        return Err("minmaxnorm requires at least one number on the stack");
+    };
+    let range = max - min;
+    let scaled: Vec<f64> = values
+        .iter()
+        .map(|x| if range == 0.0 { 0.0 } else { (x - min) / range })
+        .collect();
+    stats::push_numbers(stack, &scaled);
+    Ok(())
+}
+
+/// Replaces the numeric run at the top of the stack with z-scores,
+/// (x - mean) / s, where s is the sample standard deviation. Needs at least
+/// two values that are not all equal. Items below the run are kept.
+///
+/// Example: [2, 4, 6] zscore -> [-1, 0, 1]
+pub fn zscore(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let values = stats::take_numeric_run(stack);
+    let (Some(mean), Some(std_dev)) = (stats::mean(&values), stats::sample_std_dev(&values)) else {
+        stats::push_numbers(stack, &values);
+        return Err("zscore requires at least two numbers on the stack");
+    };
+    if std_dev == 0.0 {
+        stats::push_numbers(stack, &values);
+        return Err("zscore: standard deviation is zero (all values are equal)");
+    }
+    let scores: Vec<f64> = values.iter().map(|x| (x - mean) / std_dev).collect();
+    stats::push_numbers(stack, &scores);
+    Ok(())
+}
+
+/// Pops a threshold t and pushes how many numbers in the run below it compare
+/// to t as `ordering` (Greater for countgt, Less for countlt, Equal for counteq).
+/// The counted numbers stay in place; NaN never matches.
+///
+/// Example: [50, 120, 200] 100 countgt -> [50, 120, 200, 2]
+pub fn count_compare(
+    stack: &mut Vec<StackItem>,
+    ordering: std::cmp::Ordering,
+) -> Result<(), &'static str> {
+    let [threshold] =
+        pop_numbers(stack).ok_or("countgt/countlt/counteq require a threshold number on top")?;
+    let run = stats::numeric_run_len(stack);
+    let count = stack[stack.len() - run..]
+        .iter()
+        .filter(|item| match item {
+            StackItem::Number(val) => val.partial_cmp(&threshold) == Some(ordering),
+            _ => false,
+        })
+        .count();
+    stack.push(StackItem::Number(count as f64));
+    Ok(())
+}
+
+/// Pops a depth n (0 = top) and prints the item at that depth.
+/// The rest of the stack is left as it was.
+pub fn peek_at(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [n] = pop_numbers(stack).ok_or("peekn requires a depth n on top of the stack")?;
+
+    if n < 0.0 || n.fract() != 0.0 || n >= stack.len() as f64 {
+        stack.push(StackItem::Number(n));
+        return Err("peekn: n must be an integer from 0 to depth - 1");
+    }
+
+    let item = &stack[stack.len() - 1 - n as usize];
+    println!("\nLevel {}: {}", n, crate::format_item(item));
+    Ok(())
+}
+
+/// Largest number of values `range` may push.
+const MAX_RANGE_LEN: usize = 100_000;
+
+/// Pushes start, start + step, ... up to stop. Both ends are inclusive, so
+/// stop itself is pushed when it is reached exactly (within rounding error).
+/// Values are computed as start + i * step so errors do not accumulate.
+/// Expected stack order: [..., start, stop, step]
+pub fn range(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let [start, stop, step] =
+        pop_numbers(stack).ok_or("range requires three numbers (start stop step) on the stack")?;
+    let operands = [start, stop, step].map(StackItem::Number);
+
+    let steps = (stop - start) / step;
+    if !steps.is_finite() || steps < 0.0 {
+        restore_operands(stack, &operands);
+        return Err("range: step must be nonzero and point from start toward stop");
+    }
+    // Allow for rounding, e.g. (1 - 0) / 0.1 = 9.999999999999998
+    let count = (steps + 1e-9).floor() + 1.0;
+    if count > MAX_RANGE_LEN as f64 {
+        restore_operands(stack, &operands);
+        return Err("range: too many values (limit is 100,000)");
+    }
+
+    let values: Vec<f64> = (0..count as usize)
+        .map(|i| start + i as f64 * step)
+        .collect();
+    stats::push_numbers(stack, &values);
+    Ok(())
+}
+
+/// Largest magnitude up to which every integer is exactly representable as f64 (2^53).
+const MAX_EXACT_INT: f64 = 9_007_199_254_740_992.0;
+
+/// Greatest common divisor of two integers (Euclid's algorithm). Always non-negative.
+fn gcd(a: i64, b: i64) -> i64 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Reduces the fraction n/d to lowest terms.
+/// Pops the denominator (d) and numerator (n), rounds both to integers,
+/// divides them by their GCD, and pushes the reduced n and d back.
+pub fn simplify(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let d_val = match stack.pop() {
+        Some(StackItem::Number(val)) => val,
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            return Err("simplify requires two numbers (numerator, denominator) on the stack");
+        }
+    };
+    let n_val = match stack.pop() {
+        Some(StackItem::Number(val)) => val,
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            stack.push(StackItem::Number(d_val));
+            return Err("simplify requires two numbers (numerator, denominator) on the stack");
+        }
+    };
+
+    if ![n_val, d_val]
+        .iter()
+        .all(|v| v.round().abs() <= MAX_EXACT_INT)
+    {
+        stack.push(StackItem::Number(n_val));
+        stack.push(StackItem::Number(d_val));
+        return Err("simplify requires finite numbers of at most 2^53 in magnitude");
+    }
+
+    let n = n_val.round() as i64;
+    let d = d_val.round() as i64;
+
+    if d == 0 {
+        stack.push(StackItem::Number(n_val));
+        stack.push(StackItem::Number(d_val));
+        return Err("simplify: denominator must not be zero");
+    }
+
+    // Keep the sign on the numerator so the denominator stays positive.
+    let divisor = gcd(n, d) * d.signum();
+    stack.push(StackItem::Number((n / divisor) as f64));
+    stack.push(StackItem::Number((d / divisor) as f64));
+    Ok(())
+}
+
+/// Sum of the decimal digits of n, e.g. 12345 -> 15.
+pub fn digit_sum(mut n: u64) -> u64 {
+    let mut sum = 0;
+    while n > 0 {
+        sum += n % 10;
+        n /= 10;
+    }
+    sum
+}
+
+/// Repeated digit sum down to a single digit, e.g. 12345 -> 15 -> 6.
+pub fn digital_root(n: u64) -> u64 {
+    // Casting out nines: n and its digit sum agree mod 9
+    if n == 0 { 0 } else { 1 + (n - 1) % 9 }
+}
+
+/// Replaces the top number with its digit sum, or its digital root if `root`.
+/// Uses the integer part of the absolute value, so -12.7 counts as 12.
+pub fn digits_of(stack: &mut Vec<StackItem>, root: bool) -> Result<(), &'static str> {
+    let [val] = pop_numbers(stack).ok_or(if root {
+        "digitalroot requires one number on the stack"
+    } else {
+        "digitsum requires one number on the stack"
+    })?;
+    // Beyond 2^53 the digits are not exact, and the u64 cast would saturate
+    if !(-MAX_EXACT_INT..=MAX_EXACT_INT).contains(&val) {
+        stack.push(StackItem::Number(val));
+        return Err("digitsum/digitalroot require a finite number of at most 2^53 in magnitude");
+    }
+    let n = val.abs().trunc() as u64;
+    let result = if root { digital_root(n) } else { digit_sum(n) };
+    stack.push(StackItem::Number(result as f64));
+    Ok(())
+}
+
+/// Swaps the last two items on the stack, but only if both are numbers.
+/// The stack is left untouched on error.
+pub fn swap_numbers_only(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    match stack {
+        [.., StackItem::Number(_), StackItem::Number(_)] => swap(stack),
+        _ => Err("swapkeep requires two numbers on top of the stack"),
+    }
+}
+
+/// Builds a complex number from its real and imaginary parts.
+///
+/// Expected stack order: [..., re, im]
+pub fn make_complex(stack: &mut Vec<StackItem>) -> Result<(), &'static str> {
+    let im = match stack.pop() {
+        Some(StackItem::Number(val)) => val,
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            return Err("mkcomplex requires two numbers (re, im) on the stack");
+        }
+    };
+    let re = match stack.pop() {
+        Some(StackItem::Number(val)) => val,
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            stack.push(StackItem::Number(im));
+            return Err("mkcomplex requires two numbers (re, im) on the stack");
+        }
+    };
+
+    stack.push(StackItem::Complex(re, im));
+    Ok(())
+}
+
+/// Turns the top number a into the imaginary number a*i.
+pub fn imaginary(stack: &mut [StackItem]) -> Result<(), &'static str> {
+    if let Some(top) = stack.last_mut()
+        && let StackItem::Number(val) = *top
+    {
+        *top = StackItem::Complex(0.0, val);
+        return Ok(());
+    }
+    Err("i requires one real number on the stack")
+}
+
+/// Replaces the angle a (in radians) with cos(a) and sin(a), the real and
+/// imaginary parts of e^(ia).
+///
+/// Resulting stack order: [..., cos(a), sin(a)]
+pub fn cis(stack: &mut Vec<StackItem>, mode: AngleMode) -> Result<(), &'static str> {
+    let angle = match stack.pop() {
+        Some(StackItem::Number(val)) => val,
+        item => {
+            if let Some(i) = item {
+                stack.push(i);
+            }
+            return Err("cis requires one number (an angle) on the stack");
+        }
+    };
+
+    let angle = match mode {
+        AngleMode::Degrees => angle.to_radians(),
+        AngleMode::Radians => angle,
+    };
+    let (sin, cos) = angle.sin_cos();
+    stack.push(StackItem::Number(cos));
+    stack.push(StackItem::Number(sin));
+    Ok(())
+}
@@ -0,0 +1,143 @@
+use super::StackItem;
+
+/// Returns how many items at the top of the stack are numbers, stopping at the
+/// first non-number (e.g., a key).
+pub fn numeric_run_len(stack: &[StackItem]) -> usize {
+    stack
+        .iter()
+        .rev()
+        .take_while(|item| matches!(item, StackItem::Number(_)))
+        .count()
+}
+
+/// Removes the contiguous numbers at the top of the stack and returns them in
+/// stack order (deepest first). Items below the run are left in place.
+pub fn take_numeric_run(stack: &mut Vec<StackItem>) -> Vec<f64> {
+    let start = stack.len() - numeric_run_len(stack);
+    stack
+        .drain(start..)
+        .map(|item| match item {
+            StackItem::Number(val) => val,
+            _ => unreachable!("numeric run only contains numbers"),
+        })
+        .collect()
+}
+
+/// Pushes values onto the stack as numbers.
+pub fn push_numbers(stack: &mut Vec<StackItem>, values: &[f64]) {
+    stack.extend(values.iter().map(|val| StackItem::Number(*val)));
+}
+
+/// Replaces the numeric run at the top of the stack with a single value computed
+/// by `reduce`. If `reduce` returns None, the run is restored and `err` is returned.
+pub fn reduce_run(
+    stack: &mut Vec<StackItem>,
+    reduce: fn(&[f64]) -> Option<f64>,
+    err: &'static str,
+) -> Result<(), &'static str> {
+    let values = take_numeric_run(stack);
+    match reduce(&values) {
+        Some(result) => {
+            stack.push(StackItem::Number(result));
+            Ok(())
+        }
+        None => {
+            push_numbers(stack, &values);
+            Err(err)
+        }
+    }
+}
+
+/// The nth root of the product of n values. None if empty or any value is non-positive.
+pub fn geometric_mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() || values.iter().any(|v| *v <= 0.0) {
+        return None;
+    }
+    // Average the logs rather than multiplying to avoid overflow.
+    let mean_ln = values.iter().map(|v| v.ln()).sum::<f64>() / values.len() as f64;
+    Some(mean_ln.exp())
+}
+
+/// n divided by the sum of reciprocals. None if empty or any value is zero.
+pub fn harmonic_mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() || values.contains(&0.0) {
+        return None;
+    }
+    let reciprocal_sum: f64 = values.iter().map(|v| 1.0 / v).sum();
+    Some(values.len() as f64 / reciprocal_sum)
+}
+
+/// Smallest value, ordered by `f64::total_cmp` so NaN handling is deterministic.
+pub fn minimum(values: &[f64]) -> Option<f64> {
+    values.iter().copied().min_by(f64::total_cmp)
+}
+
+/// Largest value, ordered by `f64::total_cmp` so NaN handling is deterministic.
+pub fn maximum(values: &[f64]) -> Option<f64> {
+    values.iter().copied().max_by(f64::total_cmp)
+}
+
+/// Sum of the squares of the values. None if empty.
+pub fn sum_of_squares(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().map(|v| v * v).sum())
+}
+
+/// Square root of the mean of the squares. None if empty.
+pub fn root_mean_square(values: &[f64]) -> Option<f64> {
+    sum_of_squares(values).map(|sum| (sum / values.len() as f64).sqrt())
+}
+
+/// Arithmetic mean. None if empty.
+pub fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Sample standard deviation (divides by n - 1). None for fewer than two values.
+pub fn sample_std_dev(values: &[f64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+    let mean = mean(values)?;
+    let squares: f64 = values.iter().map(|v| (v - mean).powi(2)).sum();
+    Some((squares / (values.len() - 1) as f64).sqrt())
+}
+
+/// Most frequent value, counting exactly equal values (==) together. Ties go
+/// to the smallest value, so the result never depends on input order.
+/// None if empty.
+pub fn statistical_mode(values: &[f64]) -> Option<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut best: Option<(f64, usize)> = None;
+    for run in sorted.chunk_by(|a, b| a == b) {
+        if best.is_none_or(|(_, count)| run.len() > count) {
+            best = Some((run[0], run.len()));
+        }
+    }
+    best.map(|(val, _)| val)
+}
+
+/// Least-squares line through the points. Returns (slope, intercept), or None
+/// if there are fewer than two points or all x values are equal.
+pub fn least_squares(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    if points.len() < 2 {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    // Centered sums are more accurate than the textbook n*sum(xy) - sum(x)*sum(y) form.
+    let sxx: f64 = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum();
+    if sxx == 0.0 {
+        return None;
+    }
+    let slope = sxy / sxx;
+    Some((slope, mean_y - slope * mean_x))
+}
@@ -1,21 +1,54 @@
-use super::{StackItem, UnaryHandler};
+use super::{StackItem, UnaryHandler, complex};
 use std::f64::consts;
 /// Applies an operation to the top f64 value on the stack, modifying it in place.
-pub fn calculate(stack: &mut Vec<StackItem>, operation: UnaryHandler) -> Result<(), &'static str> {
-    let val = match stack.last_mut() {
-        Some(StackItem::Number(val)) => val,
-        _ => return Err("Unary operator requires one number on the stack"),
+/// A complex top value uses the complex form of `token` instead.
+pub fn calculate(
+    stack: &mut [StackItem],
+    operation: UnaryHandler,
+    token: &str,
+) -> Result<(), &'static str> {
+    let top = match stack.last_mut() {
+        Some(top) => top,
+        None => return Err("Unary operator requires one number on the stack"),
     };
 
     // Read the value, perform the operation, and write back to the reference
-    *val = operation(*val);
+    *top = match *top {
+        StackItem::Number(val) => StackItem::Number(operation(val)),
+        StackItem::Complex(re, im) => {
+            complex::unary(token, (re, im)).ok_or("Operator does not support complex numbers")?
+        }
+        StackItem::Key(_) => return Err("Unary operator requires one number on the stack"),
+    };
 
     Ok(())
 }
 
+/// Rounds to the nearest integer, sending ties to the even neighbour (banker's
+/// rounding): 2.5 -> 2, 3.5 -> 4. Half-away-from-zero rounding would give 3 and 4,
+/// which biases sums of many rounded values upward.
+pub fn round_half_even(x: f64) -> f64 {
+    x.round_ties_even()
+}
+
 pub fn rad_to_deg(rad: f64) -> f64 {
     rad * 180.0 / consts::PI
 }
 pub fn deg_to_rad(deg: f64) -> f64 {
     deg * consts::PI / 180.0
 }
+
+/// Degree-mode versions of the trig operators: sin/cos/tan take degrees and the
+/// inverse functions return degrees. None for operators without an angle.
+pub fn in_degrees(token: &str) -> Option<UnaryHandler> {
+    let handler: UnaryHandler = match token {
+        "sin" => |a| deg_to_rad(a).sin(),
+        "cos" => |a| deg_to_rad(a).cos(),
+        "tan" => |a| deg_to_rad(a).tan(),
+        "asin" => |a| rad_to_deg(a.asin()),
+        "acos" => |a| rad_to_deg(a.acos()),
+        "atan" => |a| rad_to_deg(a.atan()),
+        _ => return None,
+    };
+    Some(handler)
+}
@@ -2,7 +2,7 @@ use kalk_rs::*;
 use std::collections::HashMap;
 use std::f64::consts;
 // A helper function to easily get the number value from the stack
-fn get_number_at_top(stack: &Vec<StackItem>) -> f64 {
+fn get_number_at_top(stack: &[StackItem]) -> f64 {
     match stack.last() {
         Some(StackItem::Number(val)) => *val,
         _ => panic!("Stack top is not a number or stack is empty"),
@@ -72,6 +72,7 @@ fn test_basic_arithmetic() {
 
 // Test: Unary and Constants
 #[test]
+#[allow(clippy::approx_constant)] // Checks the pi constant against its leading digits.
 fn test_unary_and_constants() {
     let mut stack = Vec::new();
     let mut storage = HashMap::new();
@@ -190,6 +191,7 @@ fn test_input_parsing() {
 }
 
 #[test]
+#[allow(clippy::needless_splitn)] // Kept in its original form.
 fn test_input_comment_stripping() {
     let input_with_comment = "10 5 + # This is a comment about the sum";
 
@@ -217,6 +219,7 @@ fn test_input_comment_stripping() {
 }
 
 #[test]
+#[allow(clippy::approx_constant)] // Literal digits are the point of the parsing test.
 fn test_standard_arabic_parsing() {
     let mut stack = Vec::new();
     let mut storage = HashMap::new();
@@ -476,3 +479,2497 @@ fn test_angle_conversions() {
     assert!(process_token(&mut stack, "rad", &mut last_answer, &mut storage).is_ok());
     assert!((get_number_at_top(&stack) - consts::PI).abs() < 1e-10);
 }
+
+#[test]
+fn test_simplify() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 6 8 simplify = 3 4
+    stack.push(StackItem::Number(6.0));
+    stack.push(StackItem::Number(8.0));
+    assert!(process_token(&mut stack, "simplify", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(stack.len(), 2);
+    assert_eq!(get_number_at_top(&stack), 4.0);
+    stack.pop();
+    assert_eq!(get_number_at_top(&stack), 3.0);
+    stack.clear();
+
+    // 10 5 simplify = 2 1
+    stack.push(StackItem::Number(10.0));
+    stack.push(StackItem::Number(5.0));
+    assert!(process_token(&mut stack, "simplify", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 1.0);
+    stack.pop();
+    assert_eq!(get_number_at_top(&stack), 2.0);
+    stack.clear();
+
+    // 3 0.2 simplify (Error: denominator rounds to zero)
+    stack.push(StackItem::Number(3.0));
+    stack.push(StackItem::Number(0.2));
+    assert!(process_token(&mut stack, "simplify", &mut last_answer, &mut storage).is_err());
+    assert_eq!(stack.len(), 2);
+    assert_eq!(get_number_at_top(&stack), 0.2);
+
+    // Values that are not exact integers in f64 are rejected, not saturated
+    for (n, d) in [(-1e19, 1.0), (1.0, -1e19), (1e19, 3.0), (f64::NAN, 1.0)] {
+        stack.clear();
+        stack.push(StackItem::Number(n));
+        stack.push(StackItem::Number(d));
+        assert!(process_token(&mut stack, "simplify", &mut last_answer, &mut storage).is_err());
+        assert_eq!(stack.len(), 2);
+        assert_eq!(get_number_at_top(&stack), d);
+    }
+}
+
+#[test]
+fn test_swap_mixed_items() {
+    let mut stack = vec![StackItem::Key("x".to_string()), StackItem::Number(5.0)];
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // "x" 5 <> -> 5 "x"
+    assert!(process_token(&mut stack, "<>", &mut last_answer, &mut storage).is_ok());
+    assert!(matches!(&stack[0], StackItem::Number(v) if *v == 5.0));
+    assert!(matches!(&stack[1], StackItem::Key(k) if k == "x"));
+
+    // "x" 5 swapkeep (Error: not both numbers), stack unchanged
+    let mut stack = vec![StackItem::Key("x".to_string()), StackItem::Number(5.0)];
+    assert!(process_token(&mut stack, "swapkeep", &mut last_answer, &mut storage).is_err());
+    assert!(matches!(&stack[0], StackItem::Key(k) if k == "x"));
+    assert_eq!(get_number_at_top(&stack), 5.0);
+
+    // 1 2 swapkeep -> 2 1
+    let mut stack = vec![StackItem::Number(1.0), StackItem::Number(2.0)];
+    assert!(process_token(&mut stack, "swapkeep", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 1.0);
+}
+
+#[test]
+fn test_describe() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // Empty stack (Error)
+    assert!(process_token(&mut stack, "type", &mut last_answer, &mut storage).is_err());
+
+    // Number on top; stack unchanged
+    stack.push(StackItem::Number(-2.5));
+    assert!(process_token(&mut stack, "type", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(stack.len(), 1);
+    assert_eq!(get_number_at_top(&stack), -2.5);
+
+    // Key on top; stack unchanged
+    stack.push(StackItem::Key("rate".to_string()));
+    assert!(process_token(&mut stack, "type", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(stack.len(), 2);
+}
+
+#[test]
+fn test_pi_multiples() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    assert!(process_token(&mut stack, "pi/4", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), consts::FRAC_PI_4);
+
+    assert!(process_token(&mut stack, "2pi", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), consts::TAU);
+
+    assert!(process_token(&mut stack, "pi/6", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), consts::FRAC_PI_6);
+}
+
+#[test]
+fn test_cis() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 0 cis = [1, 0]
+    stack.push(StackItem::Number(0.0));
+    assert!(process_token(&mut stack, "cis", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(stack.len(), 2);
+    assert_eq!(get_number_at_top(&stack), 0.0); // sin on top
+    stack.pop();
+    assert_eq!(get_number_at_top(&stack), 1.0); // cos below
+    stack.clear();
+
+    // pi/2 cis = [~0, 1]
+    assert!(process_token(&mut stack, "pi/2", &mut last_answer, &mut storage).is_ok());
+    assert!(process_token(&mut stack, "cis", &mut last_answer, &mut storage).is_ok());
+    assert!((get_number_at_top(&stack) - 1.0).abs() < 1e-15);
+    stack.pop();
+    assert!(get_number_at_top(&stack).abs() < 1e-15);
+}
+
+#[test]
+fn test_complex_arithmetic() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 0 1 mkcomplex 2 ** = i^2 = -1 (collapses back to a real number)
+    for token in ["0", "1", "mkcomplex", "2", "**"] {
+        assert!(process_token(&mut stack, token, &mut last_answer, &mut storage).is_ok());
+    }
+    assert_eq!(stack.len(), 1);
+    assert_eq!(get_number_at_top(&stack), -1.0);
+    stack.clear();
+
+    // 3+4i abs = 5
+    for token in ["3", "4", "mkcomplex", "abs"] {
+        assert!(process_token(&mut stack, token, &mut last_answer, &mut storage).is_ok());
+    }
+    assert_eq!(get_number_at_top(&stack), 5.0);
+    stack.clear();
+
+    // 1 2 i + = 1+2i
+    for token in ["1", "2", "i", "+"] {
+        assert!(process_token(&mut stack, token, &mut last_answer, &mut storage).is_ok());
+    }
+    assert!(matches!(stack.last(), Some(StackItem::Complex(re, im)) if *re == 1.0 && *im == 2.0));
+
+    // i^2 * i = -i
+    stack.clear();
+    for token in ["0", "1", "mkcomplex", "2", "**", "1", "i", "*"] {
+        assert!(process_token(&mut stack, token, &mut last_answer, &mut storage).is_ok());
+    }
+    assert!(matches!(stack.last(), Some(StackItem::Complex(re, im)) if *re == 0.0 && *im == -1.0));
+
+    // Non complex-aware operators refuse complex operands and leave the stack intact
+    assert!(process_token(&mut stack, "sin", &mut last_answer, &mut storage).is_err());
+    assert_eq!(stack.len(), 1);
+}
+
+#[test]
+fn test_complex_parts() {
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 3+4i real = 3, imag = 4
+    let mut stack = vec![StackItem::Complex(3.0, 4.0)];
+    assert!(process_token(&mut stack, "real", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 3.0);
+
+    let mut stack = vec![StackItem::Complex(3.0, 4.0)];
+    assert!(process_token(&mut stack, "imag", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 4.0);
+
+    // 3+4i conj = 3-4i
+    let mut stack = vec![StackItem::Complex(3.0, 4.0)];
+    assert!(process_token(&mut stack, "conj", &mut last_answer, &mut storage).is_ok());
+    assert!(matches!(stack.last(), Some(StackItem::Complex(re, im)) if *re == 3.0 && *im == -4.0));
+
+    // Plain numbers have a zero imaginary part
+    let mut stack = vec![StackItem::Number(5.0)];
+    assert!(process_token(&mut stack, "real", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 5.0);
+    assert!(process_token(&mut stack, "imag", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 0.0);
+}
+
+#[test]
+fn test_matches_for_prefix() {
+    let mut storage = HashMap::new();
+    assert_eq!(matches_for_prefix("at", &storage), vec!["atan", "atan2"]);
+
+    // Stored keys sharing the prefix are included, in sorted order
+    storage.insert("attempt".to_string(), 1.0);
+    storage.insert("rate".to_string(), 2.0);
+    assert_eq!(
+        matches_for_prefix("at", &storage),
+        vec!["atan", "atan2", "attempt"]
+    );
+    assert!(matches_for_prefix("zzz", &storage).is_empty());
+}
+
+#[test]
+fn test_reverse_operand_binaries() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 10 3 rsub = 3 - 10 = -7
+    stack.push(StackItem::Number(10.0));
+    stack.push(StackItem::Number(3.0));
+    assert!(process_token(&mut stack, "rsub", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), -7.0);
+
+    // 10 2 rdiv = 2 / 10 = 0.2
+    stack.push(StackItem::Number(10.0));
+    stack.push(StackItem::Number(2.0));
+    assert!(process_token(&mut stack, "rdiv", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 0.2);
+
+    // 2 3 rpow = 3^2 = 9
+    stack.push(StackItem::Number(2.0));
+    stack.push(StackItem::Number(3.0));
+    assert!(process_token(&mut stack, "rpow", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 9.0);
+}
+
+#[test]
+fn test_dice_notation() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("42 seed").is_ok());
+
+    // 3d6 always lands in 3..=18
+    for _ in 0..200 {
+        assert!(calc.process_token("3d6").is_ok());
+        let roll = get_number_at_top(&calc.stack);
+        assert!((3.0..=18.0).contains(&roll));
+        assert_eq!(roll.fract(), 0.0);
+    }
+
+    // 1d1 and d1 always equal 1
+    assert!(calc.process_token("1d1").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    assert!(calc.process_token("d1").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+
+    // Same seed, same rolls
+    let mut a = Calculator::new();
+    let mut b = Calculator::new();
+    assert!(a.process_line("7 seed 4d20 d100").is_ok());
+    assert!(b.process_line("7 seed 4d20 d100").is_ok());
+    assert_eq!(get_number_at_top(&a.stack), get_number_at_top(&b.stack));
+
+    // Zero dice or zero sides is rejected
+    assert!(calc.process_token("0d6").is_err());
+    assert!(calc.process_token("3d0").is_err());
+
+    // Seeds that would not map to distinct sequences are rejected and restored
+    for line in ["c -1 seed", "c 2.5 seed", "c 1e19 seed", "c inf seed"] {
+        assert!(calc.process_line(line).is_err(), "{line}");
+        assert_eq!(calc.stack.len(), 1, "{line}");
+    }
+}
+
+#[test]
+fn test_macros_and_dowhile() {
+    let mut calc = Calculator::new();
+
+    // Quoted keys may contain spaces, so a macro body is a full line of tokens
+    assert!(calc.process_line("\"1 +\" \"inc\" def").is_ok());
+    assert!(calc.process_line("5 inc").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 6.0);
+    calc.stack.clear();
+
+    // Count up from 0 while the counter minus 5 is nonzero
+    assert!(calc.process_line("\"5 -\" \"notfive\" def").is_ok());
+    assert!(calc.process_line("0 \"inc\" \"notfive\" dowhile").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 5.0);
+    calc.stack.clear();
+
+    // An always-true condition hits the iteration cap
+    assert!(calc.process_line("\"1\" \"forever\" def").is_ok());
+    assert_eq!(
+        calc.process_line("0 \"inc\" \"forever\" dowhile"),
+        Err("dowhile: iteration limit (1,000,000) exceeded")
+    );
+
+    // Unknown macro names leave the keys in place
+    calc.stack.clear();
+    assert!(calc.process_line("\"inc\" \"nope\" dowhile").is_err());
+    assert_eq!(calc.stack.len(), 2);
+
+    // A body failing on a later iteration restores the stack and the keys
+    calc.stack.clear();
+    assert!(calc.process_line("\"+\" \"add\" def").is_ok());
+    assert!(
+        calc.process_line("1 2 3 \"add\" \"forever\" dowhile")
+            .is_err()
+    );
+    assert_eq!(calc.stack.len(), 5);
+    assert!(stack_equals(&calc.stack[..3], &[1.0, 2.0, 3.0]));
+}
+
+#[test]
+fn test_from_digits() {
+    let mut calc = Calculator::new();
+
+    // "FF" 16 fromdigits = 255
+    assert!(calc.process_line("\"FF\" 16 fromdigits").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 255.0);
+
+    // "1011" 2 fromdigits = 11
+    assert!(calc.process_line("\"1011\" 2 fromdigits").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 11.0);
+    calc.stack.clear();
+
+    // "12" 2 fromdigits (Error: 2 is not a binary digit), stack unchanged
+    assert_eq!(
+        calc.process_line("\"12\" 2 fromdigits"),
+        Err("fromdigits: invalid digit for base")
+    );
+    assert_eq!(calc.stack.len(), 2);
+
+    // Base out of range
+    calc.stack.clear();
+    assert!(calc.process_line("\"1\" 37 fromdigits").is_err());
+}
+
+#[test]
+fn test_save_and_load_stack() {
+    let mut calc = Calculator::new();
+
+    // 1 2 3 "a" savestack c "a" loadstack -> [1, 2, 3]
+    assert!(calc.process_line("1 2 3 \"a\" savestack").is_ok());
+    assert_eq!(calc.stack.len(), 3);
+    assert!(calc.process_line("c").is_ok());
+    assert!(calc.stack.is_empty());
+    assert!(calc.process_line("\"a\" loadstack").is_ok());
+    assert_eq!(calc.stack.len(), 3);
+    assert_eq!(get_number_at_top(&calc.stack), 3.0);
+    assert!(matches!(calc.stack[0], StackItem::Number(v) if v == 1.0));
+
+    // Unknown name (Error), key left in place
+    calc.stack.clear();
+    assert!(calc.process_line("\"missing\" loadstack").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_exp2() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 10 exp2 = 1024
+    stack.push(StackItem::Number(10.0));
+    assert!(process_token(&mut stack, "exp2", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 1024.0);
+
+    // 0 exp2 = 1
+    stack.push(StackItem::Number(0.0));
+    assert!(process_token(&mut stack, "exp2", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 1.0);
+
+    // -1 exp2 = 0.5
+    stack.push(StackItem::Number(-1.0));
+    assert!(process_token(&mut stack, "exp2", &mut last_answer, &mut storage).is_ok());
+    assert_eq!(get_number_at_top(&stack), 0.5);
+}
+
+#[test]
+fn test_ln1p_expm1_precision() {
+    let mut stack = Vec::new();
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // 1e-15 expm1 ~= 1e-15 ...
+    stack.push(StackItem::Number(1e-15));
+    assert!(process_token(&mut stack, "expm1", &mut last_answer, &mut storage).is_ok());
+    assert!((get_number_at_top(&stack) - 1e-15).abs() < 1e-28);
+
+    // ... while the naive 1e-15 exp 1 - loses most of the digits
+    stack.push(StackItem::Number(1e-15));
+    assert!(process_token(&mut stack, "exp", &mut last_answer, &mut storage).is_ok());
+    stack.push(StackItem::Number(1.0));
+    assert!(process_token(&mut stack, "-", &mut last_answer, &mut storage).is_ok());
+    assert!((get_number_at_top(&stack) - 1e-15).abs() > 1e-17);
+
+    // 1e-15 ln1p ~= 1e-15
+    stack.push(StackItem::Number(1e-15));
+    assert!(process_token(&mut stack, "ln1p", &mut last_answer, &mut storage).is_ok());
+    assert!((get_number_at_top(&stack) - 1e-15).abs() < 1e-28);
+}
+
+#[test]
+fn test_day_of_week() {
+    // 2000-01-01 was a Saturday, 2024-02-29 a Thursday
+    assert_eq!(day_of_week(2000, 1, 1), 6);
+    assert_eq!(day_of_week(2024, 2, 29), 4);
+    assert_eq!(day_of_week(1970, 1, 1), 4);
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("2000 1 1 dow").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 6.0);
+    calc.stack.clear();
+
+    // 2023-02-29 does not exist (Error), operands restored
+    assert!(calc.process_line("2023 2 29 dow").is_err());
+    assert_eq!(calc.stack.len(), 3);
+    calc.stack.clear();
+
+    // Month out of range (Error)
+    assert!(calc.process_line("2024 13 1 dow").is_err());
+
+    // Years too large to be exact are rejected and restored
+    for line in ["c -1e20 1 1 dow", "c 1e20 3 1 dow", "c nan 1 1 dow"] {
+        assert!(calc.process_line(line).is_err(), "{line}");
+        assert_eq!(calc.stack.len(), 3, "{line}");
+    }
+
+    // Any i64 year works in the public helper
+    assert_eq!(
+        day_of_week(2024, 1, 1),
+        day_of_week(2024 + 400 * 1000, 1, 1)
+    );
+    assert!(day_of_week(i64::MIN, 1, 1) < 7);
+    assert!(day_of_week(i64::MAX, 12, 31) < 7);
+}
+
+#[test]
+fn test_geometric_and_harmonic_mean() {
+    assert!((geometric_mean(&[1.0, 4.0, 16.0]).unwrap() - 4.0).abs() < 1e-12);
+    assert!((harmonic_mean(&[1.0, 2.0, 4.0]).unwrap() - 12.0 / 7.0).abs() < 1e-12);
+    assert_eq!(geometric_mean(&[]), None);
+    assert_eq!(geometric_mean(&[2.0, -1.0]), None);
+    assert_eq!(harmonic_mean(&[]), None);
+
+    // Only the numbers above the key are consumed
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"k\" 1 4 16 geomean").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert!((get_number_at_top(&calc.stack) - 4.0).abs() < 1e-12);
+    calc.stack.clear();
+
+    assert!(calc.process_line("1 2 4 harmean").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 1.714).abs() < 1e-3);
+    calc.stack.clear();
+
+    // Empty stack and non-positive values (Error), stack restored
+    assert!(calc.process_line("geomean").is_err());
+    assert!(calc.process_line("4 0 geomean").is_err());
+    assert_eq!(calc.stack.len(), 2);
+}
+
+#[test]
+fn test_swap_with_answer() {
+    let mut calc = Calculator::new();
+
+    // No answer yet (Error)
+    assert!(calc.process_line("1 x<>a").is_err());
+    calc.stack.clear();
+
+    // Answer is 5; 10 x<>a puts 5 on top and 10 in the register
+    assert!(calc.process_line("5").is_ok());
+    calc.stack.clear();
+    assert!(calc.process_line("10 x<>a").is_ok());
+    assert!(stack_equals(&calc.stack, &[5.0]));
+
+    // The exchanged value outlives the end of its line and later work
+    assert!(calc.process_line("2 *").is_ok());
+    assert!(calc.process_line("a").is_ok());
+    assert!(stack_equals(&calc.stack, &[10.0, 10.0]));
+
+    // Once recalled, line results update the answer again
+    assert!(calc.process_line("+").is_ok());
+    assert_eq!(calc.last_answer, Some(20.0));
+}
+
+#[test]
+fn test_running_total() {
+    let mut calc = Calculator::new();
+
+    // Off by default: entered numbers are not tracked
+    assert!(calc.process_line("5").is_ok());
+    assert_eq!(calc.running_total, None);
+    assert!(calc.process_line("total?").is_err());
+
+    // Only entered numbers count, not operator results
+    assert!(calc.process_line("total 10 2.5 + 4").is_ok());
+    assert_eq!(calc.running_total, Some(16.5));
+    assert!(calc.process_line("total?").is_ok());
+    assert_eq!(calc.stack.len(), 3); // numbers are not consumed
+
+    // totalc resets, total turns the mode off again
+    assert!(calc.process_line("totalc 1").is_ok());
+    assert_eq!(calc.running_total, Some(1.0));
+    assert!(calc.process_line("total").is_ok());
+    assert_eq!(calc.running_total, None);
+}
+
+#[test]
+fn test_assert() {
+    let mut calc = Calculator::new();
+
+    // 4 2 2 * assert passes and consumes both operands
+    assert!(calc.process_line("4 2 2 * assert").is_ok());
+    assert!(calc.stack.is_empty());
+
+    // 5 2 2 * assert fails and restores the operands
+    assert_eq!(
+        calc.process_line("5 2 2 * assert"),
+        Err("assertion failed: expected and actual values differ")
+    );
+    assert_eq!(
+        calc.error_detail.as_deref(),
+        Some("assertion failed: expected 5, got 4")
+    );
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(get_number_at_top(&calc.stack), 4.0);
+    calc.stack.clear();
+
+    // Floating point noise is tolerated
+    assert!(calc.process_line("0.3 0.1 0.2 + assert").is_ok());
+}
+
+#[test]
+fn test_operands_restored_on_error() {
+    let mut storage = HashMap::new();
+    let mut last_answer = None;
+
+    // Each case errors and must leave the stack exactly as it was before
+    let cases: Vec<(Vec<StackItem>, &str)> = vec![
+        (vec![StackItem::Number(-1.0)], "!"),
+        (vec![StackItem::Number(21.0)], "!"),
+        (vec![StackItem::Key("x".to_string())], "!"),
+        (vec![StackItem::Number(3.0), StackItem::Number(5.0)], "P"),
+        (
+            vec![StackItem::Key("x".to_string()), StackItem::Number(5.0)],
+            "P",
+        ),
+        (vec![StackItem::Number(5.0)], "P"),
+        (vec![StackItem::Number(3.0), StackItem::Number(5.0)], "C"),
+        (
+            vec![StackItem::Key("x".to_string()), StackItem::Number(5.0)],
+            "C",
+        ),
+        (vec![StackItem::Number(200.0), StackItem::Number(5.0)], "C"),
+        (
+            vec![StackItem::Number(1.0), StackItem::Key("x".to_string())],
+            "+",
+        ),
+        (
+            vec![StackItem::Key("x".to_string()), StackItem::Number(1.0)],
+            "+",
+        ),
+        (vec![StackItem::Number(1.0)], "-"),
+        (
+            vec![StackItem::Complex(1.0, 1.0), StackItem::Number(2.0)],
+            "atan2",
+        ),
+    ];
+
+    for (original, token) in cases {
+        let mut stack = original.clone();
+        assert!(process_token(&mut stack, token, &mut last_answer, &mut storage).is_err());
+        assert_eq!(
+            format!("{:?}", stack),
+            format!("{:?}", original),
+            "token {}",
+            token
+        );
+    }
+}
+
+#[test]
+fn test_min_max_all() {
+    let mut calc = Calculator::new();
+
+    // [3, 7, 1, 9] maxall = [9]
+    assert!(calc.process_line("3 7 1 9 maxall").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 9.0);
+    calc.stack.clear();
+
+    // [3, 7, 1, 9] minall = [1], key below is preserved
+    assert!(calc.process_line("\"k\" 3 7 1 9 minall").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    calc.stack.clear();
+
+    // No numbers (Error)
+    assert!(calc.process_line("minall").is_err());
+    assert!(calc.process_line("\"k\" maxall").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_rpn_to_infix() {
+    assert_eq!(
+        rpn_to_infix(&["3", "4", "+", "2", "*"]),
+        Ok("(3 + 4) * 2".to_string())
+    );
+    assert_eq!(rpn_to_infix(&["2", "3", "**"]), Ok("2 ** 3".to_string()));
+
+    // Only the parentheses that change the meaning are kept
+    assert_eq!(
+        rpn_to_infix(&["1", "2", "3", "*", "+"]),
+        Ok("1 + 2 * 3".to_string())
+    );
+    assert_eq!(
+        rpn_to_infix(&["1", "2", "3", "-", "-"]),
+        Ok("1 - (2 - 3)".to_string())
+    );
+    assert_eq!(
+        rpn_to_infix(&["1", "2", "-", "3", "-"]),
+        Ok("1 - 2 - 3".to_string())
+    );
+    assert_eq!(
+        rpn_to_infix(&["2", "3", "**", "4", "**"]),
+        Ok("(2 ** 3) ** 4".to_string())
+    );
+
+    // Other operators become function calls
+    assert_eq!(
+        rpn_to_infix(&["pi", "2", "/", "sin", "8", "2", "log", "+"]),
+        Ok("sin(pi / 2) + log(8, 2)".to_string())
+    );
+
+    assert!(rpn_to_infix(&["1", "+"]).is_err());
+    assert!(rpn_to_infix(&["1", "2"]).is_err());
+    assert!(rpn_to_infix(&["1", "2", "<>"]).is_err());
+}
+
+#[test]
+fn test_render_prompt() {
+    let stack = vec![StackItem::Number(1234.5), StackItem::Key("k".to_string())];
+
+    assert_eq!(
+        render_prompt(DEFAULT_PROMPT, &stack, None),
+        "Stack: [1,234.5, \"k\"]\n> "
+    );
+    assert_eq!(render_prompt("{depth}> ", &stack, None), "2> ");
+    assert_eq!(render_prompt("ans={ans} ", &stack, Some(42.0)), "ans=42 ");
+    assert_eq!(render_prompt("ans={ans} ", &stack, None), "ans=none ");
+    assert_eq!(render_prompt("{{depth}} {x}", &stack, None), "{depth} {x}");
+
+    // The prompt command stores the template
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"[{depth}] \" prompt").is_ok());
+    assert_eq!(calc.prompt, "[{depth}] ");
+    assert!(calc.process_line("\"default\" prompt").is_ok());
+    assert_eq!(calc.prompt, DEFAULT_PROMPT);
+}
+
+#[test]
+fn test_sum_of_squares_and_rms() {
+    let mut calc = Calculator::new();
+
+    // [1, 2, 3] sumsq = [14]
+    assert!(calc.process_line("1 2 3 sumsq").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 14.0);
+    calc.stack.clear();
+
+    // [3, 4] rms = sqrt(12.5)
+    assert!(calc.process_line("3 4 rms").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 12.5_f64.sqrt()).abs() < 1e-12);
+    calc.stack.clear();
+
+    // Empty stack (Error)
+    assert!(calc.process_line("rms").is_err());
+    assert!(calc.process_line("sumsq").is_err());
+    assert!(calc.stack.is_empty());
+}
+
+#[test]
+fn test_bury() {
+    let mut calc = Calculator::new();
+
+    // [1, 2, 3, 4] 2 bury = [1, 2, 4, 3]
+    assert!(calc.process_line("1 2 3 4 2 bury").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [1.0, 2.0, 4.0, 3.0].map(StackItem::Number))
+    );
+
+    // 4 bury moves the top to the bottom: [3, 1, 2, 4]
+    assert!(calc.process_line("4 bury").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [3.0, 1.0, 2.0, 4.0].map(StackItem::Number))
+    );
+
+    // 0 bury is a no-op
+    assert!(calc.process_line("0 bury").is_ok());
+    assert_eq!(calc.stack.len(), 4);
+    assert_eq!(get_number_at_top(&calc.stack), 4.0);
+
+    // Too deep (Error), level stays on the stack
+    assert!(calc.process_line("5 bury").is_err());
+    assert_eq!(calc.stack.len(), 5);
+
+    // A huge level is restored as entered, not as a saturated count
+    assert!(calc.process_line("c 1 2 1e20 bury").is_err());
+    assert_eq!(get_number_at_top(&calc.stack), 1e20);
+}
+
+#[test]
+fn test_def_rejects_empty_body_and_builtin_names() {
+    let mut calc = Calculator::new();
+
+    // Empty and whitespace-only bodies (Error), keys left in place
+    assert_eq!(
+        calc.process_line("\"\" \"noop\" def"),
+        Err("def: macro body cannot be empty")
+    );
+    assert_eq!(calc.stack.len(), 2);
+    calc.stack.clear();
+    assert!(calc.process_line("\"   \" \"noop\" def").is_err());
+    calc.stack.clear();
+
+    // Shadowing an operator (Error)
+    assert_eq!(
+        calc.process_line("\"2 *\" \"sqrt\" def"),
+        Err("def: name shadows a built-in operator")
+    );
+    assert!(calc.macros.is_empty());
+    calc.stack.clear();
+
+    // A valid definition still works
+    assert!(
+        calc.process_line("\"2 *\" \"double\" def 21 double")
+            .is_ok()
+    );
+    assert_eq!(get_number_at_top(&calc.stack), 42.0);
+}
+
+#[test]
+fn test_significant_figures() {
+    assert_eq!(round_sig(123456.0, 3), 123000.0);
+    assert_eq!(round_sig(0.0012345, 2), 0.0012);
+    assert_eq!(round_sig(-987.6, 2), -990.0);
+    assert_eq!(round_sig(0.0, 3), 0.0);
+
+    // Subnormal inputs stay finite
+    let tiny = round_sig(1.23456e-310, 3);
+    assert!(tiny.is_finite());
+    assert!((tiny - 1.23e-310).abs() < 1e-313);
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("123456 3 sigfig").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 123000.0);
+    calc.stack.clear();
+
+    assert!(calc.process_line("0 3 sigfig").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 0.0);
+    calc.stack.clear();
+
+    assert!(calc.process_line("1e-310 3 sigfig").is_ok());
+    assert!(get_number_at_top(&calc.stack).is_finite());
+    calc.stack.clear();
+
+    // n out of range (Error), operands restored
+    assert!(calc.process_line("1.5 0 sigfig").is_err());
+    assert!(calc.process_line("16 sigfig").is_err());
+    assert_eq!(calc.stack.len(), 3);
+}
+
+#[test]
+fn test_underflow_hints() {
+    let mut calc = Calculator::new();
+
+    // Off by default
+    assert!(calc.process_line("1 +").is_err());
+    assert_eq!(calc.last_hint, None);
+    calc.stack.clear();
+
+    // + on a one-item stack explains what is missing and keeps the item
+    assert!(calc.process_line("hints").is_ok());
+    assert!(calc.process_line("5 +").is_err());
+    assert_eq!(
+        calc.last_hint.as_deref(),
+        Some("'+' needs 2 numbers but the stack has 1 - try entering more values first")
+    );
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 5.0);
+
+    // Errors that are not underflow get no hint
+    assert!(calc.process_line("nope").is_err());
+    assert_eq!(calc.last_hint, None);
+    assert_eq!(
+        underflow_hint("sqrt", 0).as_deref(),
+        Some("'sqrt' needs 1 number but the stack has 0 - try entering more values first")
+    );
+    assert_eq!(underflow_hint("+", 2), None);
+}
+
+#[test]
+fn test_usage_profile() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 2 + 3 + 4 * sqrt").is_ok());
+    // Failed operators are not counted
+    assert!(calc.process_line("c -").is_err());
+
+    assert_eq!(calc.usage_counts.get("+"), Some(&2));
+    assert_eq!(calc.usage_counts.get("-"), None);
+    assert_eq!(
+        calc.usage_report(),
+        vec![("+", 2), ("*", 1), ("c", 1), ("sqrt", 1)]
+    );
+}
+
+#[test]
+fn test_floor_division() {
+    let mut stack: Vec<StackItem> = Vec::new();
+    let mut last_answer = None;
+    let mut storage = HashMap::new();
+
+    for (a, b, expected) in [
+        (7.0, 2.0, 3.0),
+        (-7.0, 2.0, -4.0),
+        (7.0, 0.0, f64::INFINITY),
+    ] {
+        stack.push(StackItem::Number(a));
+        stack.push(StackItem::Number(b));
+        assert!(process_token(&mut stack, "//", &mut last_answer, &mut storage).is_ok());
+        assert_eq!(get_number_at_top(&stack), expected);
+        stack.clear();
+    }
+}
+
+#[test]
+fn test_autolog() {
+    let path = std::env::temp_dir().join(format!("kalk-autolog-{}.txt", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut calc = Calculator::new();
+
+    assert!(
+        calc.process_line(&format!("\"{}\" autolog", path.display()))
+            .is_ok()
+    );
+    assert!(calc.process_line("1 2 +").is_ok());
+    assert!(calc.process_line("4 *").is_ok());
+    // Failed lines are not logged
+    assert!(calc.process_line("nope").is_err());
+    assert!(calc.process_line("2 /").is_ok());
+
+    // Nothing more is written once it is turned off
+    assert!(calc.process_line("\"off\" autolog 100").is_ok());
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contents, "3\n12\n6\n");
+}
+
+#[test]
+fn test_cast_mode() {
+    assert_eq!(
+        format_base(2.9, "hex", CastMode::Trunc),
+        Ok("0x2".to_string())
+    );
+    assert_eq!(
+        format_base(2.9, "hex", CastMode::Round),
+        Ok("0x3".to_string())
+    );
+    assert_eq!(to_integer(-2.5, CastMode::Floor), Some(-3));
+    assert_eq!(to_integer(-2.5, CastMode::Ceil), Some(-2));
+    assert_eq!(to_integer(f64::NAN, CastMode::Trunc), None);
+    assert_eq!(to_integer(1e19, CastMode::Trunc), None);
+
+    let mut calc = Calculator::new();
+    assert_eq!(calc.cast_mode, CastMode::Trunc);
+    assert!(calc.process_line("\"round\" castmode").is_ok());
+    assert_eq!(calc.cast_mode, CastMode::Round);
+    assert!(calc.stack.is_empty());
+
+    // Unknown modes (Error), key left in place
+    assert!(calc.process_line("\"up\" castmode").is_err());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(calc.cast_mode, CastMode::Round);
+}
+
+#[test]
+fn test_alias() {
+    let mut calc = Calculator::new();
+
+    // "fact" runs "!"
+    assert!(calc.process_line("\"fact\" \"!\" alias").is_ok());
+    assert!(calc.process_line("5 fact").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 120.0);
+    calc.stack.clear();
+
+    // Aliases to macros work too
+    assert!(
+        calc.process_line("\"2 *\" \"double\" def \"dbl\" \"double\" alias 4 dbl")
+            .is_ok()
+    );
+    assert_eq!(get_number_at_top(&calc.stack), 8.0);
+    calc.stack.clear();
+
+    // Unknown target and shadowed built-in (Error), keys left in place
+    assert_eq!(
+        calc.process_line("\"f\" \"nope\" alias"),
+        Err("alias: target must be an existing operator or macro")
+    );
+    assert_eq!(calc.stack.len(), 2);
+    calc.stack.clear();
+    assert_eq!(
+        calc.process_line("\"sqrt\" \"!\" alias"),
+        Err("alias: name shadows a built-in operator")
+    );
+}
+
+#[test]
+fn test_softclear() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 2 3 softclear").is_ok());
+    assert!(calc.stack.is_empty());
+    assert!(calc.process_line("a").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 3.0);
+}
+
+#[test]
+fn test_det2() {
+    let mut calc = Calculator::new();
+
+    // [[1, 2], [3, 4]] -> 1*4 - 2*3 = -2
+    assert!(calc.process_line("1 2 3 4 det2").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), -2.0);
+    calc.stack.clear();
+
+    // Identity
+    assert!(calc.process_line("1 0 0 1 det2").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    calc.stack.clear();
+
+    // Too few numbers (Error), stack untouched
+    assert!(calc.process_line("1 2 3 det2").is_err());
+    assert_eq!(calc.stack.len(), 3);
+}
+
+#[test]
+fn test_angle_mode_and_normangle() {
+    let mut calc = Calculator::new();
+
+    // Radian mode (default): 3pi -> pi
+    assert!(calc.process_line("3 pi * normangle").is_ok());
+    assert!((get_number_at_top(&calc.stack) - consts::PI).abs() < 1e-12);
+    calc.stack.clear();
+
+    // Degree mode: 370 -> 10, -90 -> 270
+    assert!(calc.process_line("\"deg\" anglemode 370 normangle").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 10.0).abs() < 1e-12);
+    assert!(calc.process_line("-90 normangle").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 270.0);
+    calc.stack.clear();
+
+    // Trig follows the mode
+    assert!(calc.process_line("30 sin").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 0.5).abs() < 1e-12);
+    assert!(calc.process_line("asin").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 30.0).abs() < 1e-9);
+    assert!(calc.process_line("1 1 atan2").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 45.0).abs() < 1e-12);
+    calc.stack.clear();
+
+    // Non-finite input (Error), value left in place
+    assert!(calc.process_line("\"rad\" anglemode").is_ok());
+    assert_eq!(calc.angle_mode, AngleMode::Radians);
+    assert!(calc.process_line("1 0 / normangle").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_linear_fit() {
+    assert_eq!(
+        least_squares(&[(1.0, 2.0), (2.0, 4.0), (3.0, 6.0)]),
+        Some((2.0, 0.0))
+    );
+    assert_eq!(least_squares(&[(1.0, 2.0)]), None);
+    assert_eq!(least_squares(&[(1.0, 2.0), (1.0, 5.0)]), None);
+
+    // Pairs are read x y x y ...: y = 3x + 1, key below is preserved
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"k\" 0 1 1 4 2 7 linfit").is_ok());
+    assert_eq!(calc.stack.len(), 3);
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    assert!(matches!(calc.stack[1], StackItem::Number(m) if (m - 3.0).abs() < 1e-12));
+    calc.stack.clear();
+
+    // Odd count or a single distinct x (Error), numbers restored
+    assert!(calc.process_line("1 2 3 linfit").is_err());
+    assert_eq!(calc.stack.len(), 3);
+    calc.stack.clear();
+    assert!(calc.process_line("2 1 2 5 linfit").is_err());
+    assert_eq!(calc.stack.len(), 4);
+}
+
+#[test]
+fn test_swap_block() {
+    let mut calc = Calculator::new();
+
+    // [1, 2, 3, 4] 2 swapblock = [3, 4, 1, 2]
+    assert!(calc.process_line("1 2 3 4 2 swapblock").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [3.0, 4.0, 1.0, 2.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    // Too few items (Error), size left on the stack
+    assert!(calc.process_line("1 2 3 2 swapblock").is_err());
+    assert_eq!(calc.stack.len(), 4);
+    assert_eq!(get_number_at_top(&calc.stack), 2.0);
+
+    // A huge size is restored as entered, not as a saturated count
+    assert!(calc.process_line("c 1 2 1e20 swapblock").is_err());
+    assert_eq!(get_number_at_top(&calc.stack), 1e20);
+}
+
+#[test]
+fn test_keep() {
+    let mut calc = Calculator::new();
+
+    // [1, 2, 3, 4, 5] 2 keep = [4, 5]
+    assert!(calc.process_line("1 2 3 4 5 2 keep").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [4.0, 5.0].map(StackItem::Number))
+    );
+
+    // More than the depth keeps everything
+    assert!(calc.process_line("5 keep").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+
+    // 0 keep empties the stack
+    assert!(calc.process_line("0 keep").is_ok());
+    assert!(calc.stack.is_empty());
+
+    // Negative count (Error)
+    assert!(calc.process_line("-1 keep").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_differences() {
+    let mut calc = Calculator::new();
+
+    // [10, 13, 11, 15] diff = [3, -2, 4], key below is preserved
+    assert!(calc.process_line("\"k\" 10 13 11 15 diff").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!(
+            "{:?}",
+            [
+                StackItem::Key("k".to_string()),
+                StackItem::Number(3.0),
+                StackItem::Number(-2.0),
+                StackItem::Number(4.0)
+            ]
+        )
+    );
+    calc.stack.clear();
+
+    // Two numbers give one difference
+    assert!(calc.process_line("5 2 diff").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), -3.0);
+
+    // One number (Error), left in place
+    calc.stack.clear();
+    assert!(calc.process_line("5 diff").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_cumulative_sum() {
+    let mut calc = Calculator::new();
+
+    // [1, 2, 3, 4] cumsum = [1, 3, 6, 10]
+    assert!(calc.process_line("1 2 3 4 cumsum").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [1.0, 3.0, 6.0, 10.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    // A single number is unchanged
+    assert!(calc.process_line("\"k\" 7 cumsum").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(get_number_at_top(&calc.stack), 7.0);
+    calc.stack.clear();
+
+    // Empty stack (Error)
+    assert!(calc.process_line("cumsum").is_err());
+}
+
+#[test]
+fn test_angle_peek() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("pi ?deg").is_ok());
+    assert!(calc.process_line("180 ?rad").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(get_number_at_top(&calc.stack), 180.0);
+
+    // Empty stack (Error)
+    calc.stack.clear();
+    assert!(calc.process_line("?deg").is_err());
+}
+
+#[test]
+fn test_physical_constants() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("c0").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 299792458.0);
+    assert!(calc.process_line("avogadro").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 6.02214076e23);
+
+    // Listing them leaves the stack alone
+    assert!(calc.process_line("constants").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+}
+
+#[test]
+fn test_exact_root() {
+    let mut calc = Calculator::new();
+
+    for (line, expected) in [
+        ("32 5 rootn", 2.0),
+        ("-32 5 rootn", -2.0),
+        ("125 3 rootn", 5.0),
+    ] {
+        assert!(calc.process_line(line).is_ok());
+        assert_eq!(get_number_at_top(&calc.stack), expected);
+    }
+    assert!(calc.process_line("10 2 rootn").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 10f64.sqrt()).abs() < 1e-12);
+    calc.stack.clear();
+
+    // n == 0 and even roots of negatives (Error), operands restored
+    assert!(calc.process_line("8 0 rootn").is_err());
+    assert_eq!(calc.stack.len(), 2);
+    calc.stack.clear();
+    assert!(calc.process_line("-16 4 rootn").is_err());
+    assert_eq!(calc.stack.len(), 2);
+}
+
+#[test]
+fn test_chain_mode() {
+    // Off: a fresh line does not see the previous answer
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("5").is_ok());
+    calc.stack.clear();
+    assert!(calc.process_line("3 +").is_err());
+
+    // On: the answer already on top is not pushed twice
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("chain").is_ok());
+    assert!(calc.stack.is_empty());
+    assert!(calc.process_line("5").is_ok());
+    assert!(calc.process_line("3 +").is_ok());
+    assert!(stack_equals(&calc.stack, &[8.0]));
+
+    // After the stack is emptied, the next line starts from the answer
+    assert!(calc.process_line("c").is_ok());
+    assert!(calc.process_line("2 *").is_ok());
+    assert!(stack_equals(&calc.stack, &[16.0]));
+
+    // Empty, display-only and mode lines push nothing, including turning chain off
+    assert!(calc.process_line("c").is_ok());
+    for line in ["  # just a comment", "session", "hints", "chain"] {
+        assert!(calc.process_line(line).is_ok(), "{line}");
+        assert!(calc.stack.is_empty(), "{line}");
+    }
+}
+
+#[test]
+fn test_export_csv() {
+    let path = std::env::temp_dir().join(format!("kalk-export-{}.csv", std::process::id()));
+    let mut calc = Calculator::new();
+
+    assert!(
+        calc.process_line(&format!("1 2.5 3 \"{}\" export", path.display()))
+            .is_ok()
+    );
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contents, "1,2.5,3\n");
+    // The stack itself is left alone
+    assert_eq!(calc.stack.len(), 3);
+
+    // Keys on the stack (Error), file name restored
+    calc.stack.clear();
+    assert!(
+        calc.process_line(&format!("1 \"k\" \"{}\" export", path.display()))
+            .is_err()
+    );
+    assert_eq!(calc.stack.len(), 3);
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_stack_lock() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("1 2 lock").is_ok());
+
+    // Mutations are refused and leave the stack as it was
+    assert_eq!(calc.process_line("+"), Err("stack is locked"));
+    assert_eq!(calc.process_line("3"), Err("stack is locked"));
+    assert_eq!(calc.process_line("c"), Err("stack is locked"));
+    assert_eq!(calc.stack.len(), 2);
+
+    // Read-only commands still work
+    assert!(calc.process_line("help").is_ok());
+    assert!(calc.process_line("hex").is_ok());
+    assert!(calc.process_line("words roman hist").is_ok());
+
+    // A quoted name for help is allowed, and help takes it off again
+    assert!(calc.process_line("\"sqrt\" help").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(calc.process_line("\"sqrt\""), Err("stack is locked"));
+
+    // Unlocked, operations resume
+    assert!(calc.process_line("unlock +").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 3.0);
+}
+
+#[test]
+fn test_number_to_words() {
+    assert_eq!(number_to_words(0), "zero");
+    assert_eq!(number_to_words(21), "twenty-one");
+    assert_eq!(number_to_words(100), "one hundred");
+    assert_eq!(
+        number_to_words(1234),
+        "one thousand two hundred thirty-four"
+    );
+    assert_eq!(number_to_words(-15), "negative fifteen");
+    assert_eq!(number_to_words(2_000_000_017), "two billion seventeen");
+
+    // The command rounds and leaves the stack alone
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("41.6 words").is_ok());
+    assert_eq!(calc.stack.len(), 1);
+    assert!(calc.process_line("1 0 / words").is_err());
+}
+
+#[test]
+fn test_roman_numerals() {
+    assert_eq!(to_roman(4).as_deref(), Some("IV"));
+    assert_eq!(to_roman(1994).as_deref(), Some("MCMXCIV"));
+    assert_eq!(to_roman(3888).as_deref(), Some("MMMDCCCLXXXVIII"));
+    assert_eq!(to_roman(0), None);
+    assert_eq!(to_roman(4000), None);
+
+    // Out of range (Error), stack untouched
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("2024 roman").is_ok());
+    assert!(calc.process_line("-5 roman").is_err());
+    assert_eq!(calc.stack.len(), 2);
+}
+
+#[test]
+fn test_capture() {
+    let mut calc = Calculator::new();
+
+    // The captured value survives later operations, on the same line or later ones
+    assert!(calc.process_line("42 capture").is_ok());
+    assert!(calc.process_line("10 5 *").is_ok());
+    assert!(calc.process_line("a").is_ok());
+    assert!(stack_equals(&calc.stack, &[42.0, 50.0, 42.0]));
+    assert!(calc.process_line("c 42 capture 10 5 * a").is_ok());
+    assert!(stack_equals(&calc.stack, &[42.0, 50.0, 42.0]));
+
+    // After the recall, line results update the answer as usual
+    assert!(calc.process_line("+").is_ok());
+    assert_eq!(calc.last_answer, Some(92.0));
+
+    // Key or empty top (Error)
+    assert!(calc.process_line("\"k\" capture").is_err());
+    calc.stack.clear();
+    assert!(calc.process_line("capture").is_err());
+}
+
+#[test]
+fn test_negated_answer() {
+    let mut calc = Calculator::new();
+
+    // No answer yet (Error)
+    assert!(calc.process_line("-a").is_err());
+
+    assert!(calc.process_line("5").is_ok());
+    assert!(calc.process_line("-a").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), -5.0);
+}
+
+#[test]
+fn test_luhn() {
+    assert!(luhn_valid("79927398713"));
+    assert!(!luhn_valid("79927398714"));
+    assert!(!luhn_valid(""));
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("4539578763621486 luhn").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    assert!(calc.process_line("4539578763621487 luhn").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 0.0);
+    calc.stack.clear();
+
+    // Fractional input (Error), left in place
+    assert!(calc.process_line("12.5 luhn").is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_input_base() {
+    let mut calc = Calculator::new();
+
+    // Base 16: FF is 255 and 10 is 16
+    assert!(calc.process_line("16 inbase FF 10").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [255.0, 16.0].map(StackItem::Number))
+    );
+    // Operators win over hex digits, and decimal floats are rejected
+    assert!(calc.process_line("c 1.5").is_err());
+    assert!(calc.stack.is_empty());
+
+    // A quoted base is decimal, so this returns to base 10
+    assert!(calc.process_line("\"10\" inbase 10").is_ok());
+    assert_eq!(calc.input_base, 10);
+    assert_eq!(get_number_at_top(&calc.stack), 10.0);
+
+    // Binary, and an invalid base (Error)
+    assert!(calc.process_line("2 inbase -101").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), -5.0);
+    assert!(calc.process_line("\"37\" inbase").is_err());
+
+    // In base 16, dice-like tokens are hex numbers
+    assert!(calc.process_line("c \"16\" inbase 3d6").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 982.0);
+
+    // Integers that f64 cannot hold exactly are rejected
+    assert!(calc.process_line("c 20000000000000").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 9_007_199_254_740_992.0);
+    assert!(calc.process_line("c 20000000000001").is_err());
+    assert!(calc.process_line("c -20000000000001").is_err());
+    assert!(calc.process_line("c FFFFFFFFFFFFFFFFFF").is_err());
+    assert!(calc.stack.is_empty());
+}
+
+#[test]
+fn test_dedup() {
+    let mut calc = Calculator::new();
+
+    // Global, not just adjacent: [1, 2, 2, 3, 1] dedup = [1, 2, 3]; key below is preserved
+    assert!(calc.process_line("\"k\" 1 2 2 3 1 dedup").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!(
+            "{:?}",
+            [
+                StackItem::Key("k".to_string()),
+                StackItem::Number(1.0),
+                StackItem::Number(2.0),
+                StackItem::Number(3.0)
+            ]
+        )
+    );
+
+    // No numbers on top (Error)
+    calc.stack.clear();
+    assert!(calc.process_line("dedup").is_err());
+}
+
+#[test]
+fn test_store_swap() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("10 \"x\" sto 99 \"x\" sto<>").is_ok());
+    assert_eq!(calc.storage.get("x"), Some(&99.0));
+    assert_eq!(calc.stack.len(), 1);
+    assert_eq!(get_number_at_top(&calc.stack), 10.0);
+    calc.stack.clear();
+
+    // Unknown key (Error), nothing changes
+    assert_eq!(
+        calc.process_line("5 \"y\" sto<>"),
+        Err("Storage key not found")
+    );
+    assert_eq!(calc.stack.len(), 2);
+    assert!(!calc.storage.contains_key("y"));
+}
+
+#[test]
+fn test_operation_limit() {
+    let mut calc = Calculator::new();
+    assert_eq!(calc.op_limit, DEFAULT_OP_LIMIT);
+
+    // An endless loop stops at the limit instead of running to the loop cap
+    assert!(
+        calc.process_line("1000 oplimit \"1 +\" \"inc\" def \"1\" \"forever\" def")
+            .is_ok()
+    );
+    assert_eq!(
+        calc.process_line("0 \"inc\" \"forever\" dowhile"),
+        Err("operation limit exceeded")
+    );
+
+    // The counter restarts with each line
+    calc.stack.clear();
+    assert!(calc.process_line("3 oplimit").is_ok());
+    for _ in 0..3 {
+        assert!(calc.process_line("1 1 + c").is_ok());
+    }
+    assert!(calc.process_line("1 1 + 1 + 1 +").is_ok());
+    assert!(calc.process_line("1 + 1 + 1 + 1 +").is_err());
+}
+
+#[test]
+fn test_histogram() {
+    let chart = render_histogram(&[1.0, 4.0, -2.0], 8);
+    let lines: Vec<&str> = chart.lines().collect();
+    assert_eq!(lines, [" 1 | ##", " 4 | ########", "-2 | ----"]);
+
+    // All-equal and all-zero values
+    assert!(
+        render_histogram(&[3.0, 3.0], 5)
+            .lines()
+            .all(|l| l.ends_with("#####"))
+    );
+    assert!(
+        render_histogram(&[0.0, 0.0], 5)
+            .lines()
+            .all(|l| l.ends_with("| "))
+    );
+
+    // The command leaves the stack alone
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("1 2 3 hist").is_ok());
+    assert_eq!(calc.stack.len(), 3);
+}
+
+#[test]
+fn test_round_half_even() {
+    let mut stack: Vec<StackItem> = Vec::new();
+    let mut last_answer = None;
+    let mut storage = HashMap::new();
+
+    for (input, expected) in [(2.5, 2.0), (3.5, 4.0), (-2.5, -2.0), (2.4, 2.0), (2.6, 3.0)] {
+        stack.push(StackItem::Number(input));
+        assert!(process_token(&mut stack, "rounde", &mut last_answer, &mut storage).is_ok());
+        assert_eq!(get_number_at_top(&stack), expected);
+        stack.clear();
+    }
+}
+
+#[test]
+fn test_load_config() {
+    let path = std::env::temp_dir().join(format!("kalk-config-{}.rc", std::process::id()));
+    std::fs::write(
+        &path,
+        "100 \"rate\" sto c # comment\nnope\n\"2 *\" \"double\" def\n",
+    )
+    .unwrap();
+
+    // The bad line only warns; the lines around it still apply
+    let mut calc = Calculator::new();
+    assert!(load_config(&path, &mut calc).is_ok());
+    std::fs::remove_file(&path).unwrap();
+    calc.stack.clear();
+    assert!(calc.process_line("\"rate\" rcl double").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 200.0);
+
+    // A missing file is an I/O error
+    assert!(load_config(&path, &mut calc).is_err());
+}
+
+#[test]
+fn test_help_follows_angle_mode() {
+    assert_eq!(
+        help_text("sin", AngleMode::Radians).as_deref(),
+        Some("a sin | Sine (a in radians)")
+    );
+    assert_eq!(
+        help_text("sin", AngleMode::Degrees).as_deref(),
+        Some("a sin | Sine (a in degrees)")
+    );
+    assert!(
+        help_text("atan2", AngleMode::Degrees)
+            .unwrap()
+            .contains("degrees")
+    );
+    // Conversions keep their wording
+    assert_eq!(
+        help_text("rad", AngleMode::Degrees).as_deref(),
+        Some("a rad | Convert angle from degrees to radians")
+    );
+    assert_eq!(help_text("nope", AngleMode::Radians), None);
+}
+
+#[test]
+fn test_peek_at_depth() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("10 20 30").is_ok());
+
+    // The index is consumed, the rest stays
+    assert!(calc.process_line("0 peekn").is_ok());
+    assert!(calc.process_line("2 peekn").is_ok());
+    assert_eq!(calc.stack.len(), 3);
+    assert_eq!(get_number_at_top(&calc.stack), 30.0);
+
+    // Out of range (Error), index left in place
+    assert!(calc.process_line("5 peekn").is_err());
+    assert_eq!(calc.stack.len(), 4);
+
+    assert_eq!(format_item(&StackItem::Number(1234.5)), "1,234.5");
+    assert_eq!(format_item(&StackItem::Key("k".to_string())), "\"k\"");
+}
+
+#[test]
+fn test_macro_trace() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"1 +\" \"inc\" def").is_ok());
+
+    // Off by default
+    assert!(calc.process_line("5 inc").is_ok());
+    assert!(calc.trace_log.is_empty());
+    calc.stack.clear();
+
+    assert!(calc.process_line("trace 5 inc").is_ok());
+    assert_eq!(calc.trace_log, ["1 -> [5, 1]", "+ -> [6]"]);
+
+    // Nested steps are indented and logged before the macro they belong to;
+    // the log restarts with each line
+    assert!(
+        calc.process_line("\"inc inc\" \"inc2\" def c 0 inc2")
+            .is_ok()
+    );
+    assert_eq!(
+        calc.trace_log,
+        [
+            "  1 -> [0, 1]",
+            "  + -> [1]",
+            "inc -> [1]",
+            "  1 -> [1, 1]",
+            "  + -> [2]",
+            "inc -> [2]"
+        ]
+    );
+}
+
+#[test]
+fn test_constant_units() {
+    assert_eq!(unit_of("c0"), Some("m/s"));
+    assert_eq!(unit_of("pi"), None);
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("c0").is_ok());
+    assert_eq!(calc.last_constant, Some("c0"));
+    assert!(calc.process_line("units?").is_ok());
+    // Read-only commands keep it
+    assert!(calc.process_line("hex units?").is_ok());
+
+    // Changing the stack forgets it (Error)
+    assert!(calc.process_line("2 *").is_ok());
+    assert_eq!(calc.last_constant, None);
+    assert!(calc.process_line("units?").is_err());
+
+    // Mathematical constants have no unit (Error)
+    assert!(calc.process_line("pi units?").is_err());
+}
+
+#[test]
+fn test_range() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 5 1 range").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [1.0, 2.0, 3.0, 4.0, 5.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    assert!(calc.process_line("0 1 0.25 range").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [0.0, 0.25, 0.5, 0.75, 1.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    // Counting down, and a stop that is not hit exactly
+    assert!(calc.process_line("10 0 -4 range").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [10.0, 6.0, 2.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    // Wrong-sign and zero steps, and too many values (Error), operands restored
+    assert!(calc.process_line("1 5 -1 range").is_err());
+    assert_eq!(calc.stack.len(), 3);
+    calc.stack.clear();
+    assert!(calc.process_line("1 5 0 range").is_err());
+    calc.stack.clear();
+    assert!(calc.process_line("0 1e6 1 range").is_err());
+    assert_eq!(calc.stack.len(), 3);
+}
+
+#[test]
+fn test_map() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 4 9 \"sqrt\" map").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [1.0, 2.0, 3.0].map(StackItem::Number))
+    );
+    calc.stack.clear();
+
+    // Macros work too; keys below the run are preserved
+    assert!(calc.process_line("\"2 **\" \"square\" def").is_ok());
+    assert!(calc.process_line("\"k\" 1 2 3 \"square\" map").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!(
+            "{:?}",
+            [
+                StackItem::Key("k".to_string()),
+                StackItem::Number(1.0),
+                StackItem::Number(4.0),
+                StackItem::Number(9.0)
+            ]
+        )
+    );
+    calc.stack.clear();
+
+    // Binary operators are rejected (Error), leaving the stack unchanged
+    assert!(calc.process_line("1 2 \"+\" map").is_err());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!(
+            "{:?}",
+            [
+                StackItem::Number(1.0),
+                StackItem::Number(2.0),
+                StackItem::Key("+".to_string())
+            ]
+        )
+    );
+}
+
+#[test]
+fn test_undo_redo() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("5 3 +").is_ok());
+    assert!(calc.process_line("undo").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [5.0, 3.0].map(StackItem::Number))
+    );
+    assert!(calc.process_line("redo").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [8.0].map(StackItem::Number))
+    );
+
+    // A new operation invalidates redo
+    assert!(calc.process_line("undo undo").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [5.0].map(StackItem::Number))
+    );
+    assert!(calc.process_line("2 *").is_ok());
+    assert!(calc.process_line("redo").is_err());
+    assert_eq!(get_number_at_top(&calc.stack), 10.0);
+
+    // Failed and read-only tokens are not undo steps
+    assert!(calc.process_line("+").is_err());
+    assert!(calc.process_line("hex").is_ok());
+    assert!(calc.process_line("undo").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!("{:?}", [5.0, 2.0].map(StackItem::Number))
+    );
+
+    let mut fresh = Calculator::new();
+    assert!(fresh.process_line("undo").is_err());
+}
+
+#[test]
+fn test_duration_input() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1:02:03").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 3723.0);
+    assert!(calc.process_line("02:30").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 150.0);
+    assert!(calc.process_line("0:01.5").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.5);
+    calc.stack.clear();
+
+    // Too many fields, out-of-range minutes and other colon tokens are rejected (Error)
+    for token in ["1:2:3:4", "1:60:00", ":30", "1:", "a:b", "1:-2"] {
+        assert_eq!(
+            calc.process_line(token),
+            Err("Unrecognized token or operator"),
+            "{}",
+            token
+        );
+    }
+    assert!(calc.stack.is_empty());
+}
+
+#[test]
+fn test_conditional_store() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("42 1 \"x\" sto?").is_ok());
+    assert_eq!(calc.storage.get("x"), Some(&42.0));
+    assert!(calc.stack.is_empty());
+
+    // A zero condition consumes the operands but leaves storage unchanged
+    assert!(calc.process_line("7 0 \"x\" sto?").is_ok());
+    assert_eq!(calc.storage.get("x"), Some(&42.0));
+    assert!(calc.stack.is_empty());
+
+    // Missing condition (Error), operands untouched
+    assert!(calc.process_line("7 \"x\" sto?").is_err());
+    assert_eq!(calc.stack.len(), 2);
+}
+
+#[test]
+fn test_classify_tokens() {
+    assert_eq!(
+        classify_tokens("3 \"x\" sqrt bogus"),
+        vec![
+            ("3".to_string(), TokenKind::Number(3.0)),
+            ("\"x\"".to_string(), TokenKind::Key("x".to_string())),
+            ("sqrt".to_string(), TokenKind::Operator("sqrt")),
+            ("bogus".to_string(), TokenKind::Unknown),
+        ]
+    );
+
+    // Same input rules as process_token: Persian digits, commas, quoted spaces, comments
+    assert_eq!(
+        classify_tokens("۱,۲۳۴ \"1 +\" 2d6 # + ignored"),
+        vec![
+            ("۱,۲۳۴".to_string(), TokenKind::Number(1234.0)),
+            ("\"1 +\"".to_string(), TokenKind::Key("1 +".to_string())),
+            ("2d6".to_string(), TokenKind::Dice(2, 6)),
+        ]
+    );
+    assert!(classify_tokens("   # only a comment").is_empty());
+}
+
+#[test]
+fn test_stack_equality() {
+    assert_eq!(StackItem::Number(2.5), StackItem::Number(2.5));
+    assert_ne!(StackItem::Number(2.5), StackItem::Number(2.6));
+    assert_eq!(
+        StackItem::Key("x".to_string()),
+        StackItem::Key("x".to_string())
+    );
+    assert_ne!(StackItem::Key("1".to_string()), StackItem::Number(1.0));
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("1 2 3 +").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0, 5.0]));
+    assert!(!stack_equals(&calc.stack, &[1.0, 5.1]));
+    assert!(!stack_equals(&calc.stack, &[1.0]));
+
+    // A key on the stack never matches a number
+    assert!(calc.process_line("\"x\"").is_ok());
+    assert!(!stack_equals(&calc.stack, &[1.0, 5.0, 0.0]));
+    assert_eq!(
+        calc.stack,
+        vec![
+            StackItem::Number(1.0),
+            StackItem::Number(5.0),
+            StackItem::Key("x".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_operators_listing() {
+    let ops = operators();
+    let find = |token: &str| ops.iter().find(|info| info.token == token).cloned();
+
+    let sqrt = find("sqrt").unwrap();
+    assert_eq!((sqrt.group, sqrt.arity), ("Unary", Arity::Fixed(1)));
+    assert_eq!(find("+").unwrap().arity, Arity::Fixed(2));
+    assert_eq!(find("pi").unwrap().arity, Arity::Fixed(0));
+    assert_eq!(find("sto").unwrap().arity, Arity::Variable);
+
+    // Sorted by group then token, and every entry is a real operator with help
+    assert!(
+        ops.windows(2)
+            .all(|w| (w[0].group, w[0].token) < (w[1].group, w[1].token))
+    );
+    for info in &ops {
+        assert_eq!(
+            classify_tokens(info.token),
+            vec![(info.token.to_string(), TokenKind::Operator(info.token))]
+        );
+        assert!(help_text(info.token, AngleMode::Radians).is_some());
+    }
+}
+
+#[test]
+fn test_percent_error() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("9.8 10 pcterr").is_ok());
+    assert!((get_number_at_top(&calc.stack) - 2.0).abs() < 1e-9);
+    calc.stack.clear();
+    assert!(calc.process_line("105 100 pcterr").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 5.0);
+    calc.stack.clear();
+
+    // Zero expected value (Error), operands restored
+    assert_eq!(
+        calc.process_line("5 0 pcterr"),
+        Err("pcterr: expected value cannot be zero")
+    );
+    assert!(stack_equals(&calc.stack, &[5.0, 0.0]));
+}
+
+#[test]
+fn test_run_script() {
+    let path = std::env::temp_dir().join(format!("kalk-run-{}.txt", std::process::id()));
+    std::fs::write(&path, "3 4 +\n2 *\n").unwrap();
+    let line = format!("\"{}\" run", path.display());
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line(&line).is_ok());
+    assert!(stack_equals(&calc.stack, &[14.0]));
+
+    // A failing line stops the script; earlier lines keep their effect (Error)
+    std::fs::write(&path, "1\n+ +\n5\n").unwrap();
+    calc.stack.clear();
+    assert!(calc.process_line(&line).is_err());
+    assert!(stack_equals(&calc.stack, &[1.0]));
+    let detail = calc.error_detail.clone().unwrap();
+    assert!(
+        detail.starts_with("run: ") && detail.contains(" line 2: "),
+        "{detail}"
+    );
+
+    // Script lines count against the operation budget of the line that ran them
+    std::fs::write(&path, "+\n").unwrap();
+    assert!(calc.process_line("3 oplimit").is_ok());
+    calc.stack.clear();
+    assert_eq!(
+        calc.process_line(&format!("1 2 3 4 {} + +", line)),
+        Err("operation limit exceeded")
+    );
+    assert!(calc.process_line("10000000 oplimit").is_ok());
+    calc.stack.clear();
+
+    // A script that runs itself hits the nesting limit instead of looping forever
+    std::fs::write(&path, &line).unwrap();
+    assert!(calc.process_line(&line).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+    calc.stack.clear();
+    assert!(calc.process_line(&line).is_err());
+    assert_eq!(calc.stack.len(), 1);
+}
+
+#[test]
+fn test_absdiff() {
+    let mut calc = Calculator::new();
+    for (line, expected) in [
+        ("5 8 absdiff", 3.0),
+        ("8 5 absdiff", 3.0),
+        ("-2 2 absdiff", 4.0),
+    ] {
+        assert!(calc.process_line(line).is_ok());
+        assert!(stack_equals(&calc.stack, &[expected]), "{}", line);
+        calc.stack.clear();
+    }
+}
+
+#[test]
+fn test_percent_display() {
+    assert_eq!(format_percent(0.1523, 2), "15.23%");
+    assert_eq!(format_percent(1.0, 0), "100%");
+    assert_eq!(format_percent(-0.05, 1), "-5.0%");
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("0.1523 ?pct").is_ok());
+    assert!(stack_equals(&calc.stack, &[0.1523]));
+
+    calc.stack.clear();
+    assert!(calc.process_line("?pct").is_err());
+}
+
+#[test]
+fn test_rotations() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 2 3 rot").is_ok());
+    assert!(stack_equals(&calc.stack, &[2.0, 3.0, 1.0]));
+    calc.stack.clear();
+    assert!(calc.process_line("1 2 3 rot-").is_ok());
+    assert!(stack_equals(&calc.stack, &[3.0, 1.0, 2.0]));
+
+    // rot then rot- is the identity, with keys too
+    calc.stack.clear();
+    assert!(calc.process_line("\"k\" 2 3 rot rot-").is_ok());
+    assert_eq!(
+        calc.stack,
+        vec![
+            StackItem::Key("k".to_string()),
+            StackItem::Number(2.0),
+            StackItem::Number(3.0)
+        ]
+    );
+
+    calc.stack.clear();
+    assert!(calc.process_line("1 2 rot-").is_err());
+    assert!(stack_equals(&calc.stack, &[1.0, 2.0]));
+}
+
+#[test]
+fn test_sig_fig_tracking() {
+    assert_eq!(count_sig_figs("12.3"), Some(3));
+    assert_eq!(count_sig_figs("0.0450"), Some(3));
+    assert_eq!(count_sig_figs("1200"), Some(2));
+    assert_eq!(count_sig_figs("1200."), Some(4));
+    assert_eq!(count_sig_figs("-6.02e23"), Some(3));
+    assert_eq!(count_sig_figs("pi"), None);
+
+    // Multiplication keeps the smaller count; addition keeps the coarser decimal place
+    assert_eq!(
+        propagate_sig_figs("*", (1.23, 3), (4.5678, 5), 1.23 * 4.5678),
+        Some(3)
+    );
+    assert_eq!(
+        propagate_sig_figs("+", (12.3, 3), (1.234, 4), 13.534),
+        Some(3)
+    );
+    assert_eq!(propagate_sig_figs("+", (99.0, 2), (1.0, 1), 100.0), Some(3));
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("1.23 4.5678 *").is_ok());
+    assert_eq!(calc.sig_warning, None);
+
+    assert!(calc.process_line("c sigtrack").is_ok());
+    assert!(calc.process_line("1.23 4.5678 *").is_ok());
+    assert!(calc.sig_warning.as_ref().unwrap().contains("5.62"));
+
+    // A result equal to its first operand is still checked
+    assert!(calc.process_line("c 1.2345 1 *").is_ok());
+    assert!(calc.sig_warning.is_some());
+    assert!(calc.process_line("c 12.345 0 +").is_ok());
+    assert!(calc.sig_warning.is_some());
+
+    // Results that do not overstate their precision, and other operators, do not warn
+    assert!(calc.process_line("c 2.0 3.0 *").is_ok());
+    assert_eq!(calc.sig_warning, None);
+    assert!(calc.process_line("c 2.0 3.0 ** 3.1 *").is_ok());
+    assert_eq!(calc.sig_warning, None);
+}
+
+#[test]
+fn test_reset() {
+    let mut calc = Calculator::new();
+    assert!(
+        calc.process_line(
+            "\"deg\" anglemode hints sigtrack 42 \"x\" sto \"1 +\" \"inc\" def 1 2 3"
+        )
+        .is_ok()
+    );
+    assert!(calc.process_line("\"16\" inbase").is_ok());
+
+    assert!(calc.process_line("reset").is_ok());
+    assert!(calc.stack.is_empty());
+    assert!(calc.storage.is_empty() && calc.macros.is_empty());
+    assert_eq!(calc.last_answer, None);
+    assert_eq!(calc.angle_mode, AngleMode::Radians);
+    assert_eq!(calc.input_base, 10);
+    assert!(!calc.hints && !calc.sig_track);
+
+    // Decimal input works again
+    assert!(calc.process_line("10").is_ok());
+    assert!(stack_equals(&calc.stack, &[10.0]));
+
+    // Inside a macro or a script, and macros still run normally afterwards
+    assert!(calc.process_line("\"reset 7\" \"r\" def r").is_ok());
+    assert!(stack_equals(&calc.stack, &[7.0]));
+    let path = std::env::temp_dir().join(format!("kalk-reset-{}.txt", std::process::id()));
+    std::fs::write(&path, "1 2\nreset\n3\n").unwrap();
+    assert!(
+        calc.process_line(&format!("\"{}\" run", path.display()))
+            .is_ok()
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(stack_equals(&calc.stack, &[3.0]));
+    assert!(calc.process_line("\"1 +\" \"inc\" def inc").is_ok());
+    assert!(stack_equals(&calc.stack, &[4.0]));
+}
+
+#[test]
+fn test_answer_mode() {
+    // Default: the answer survives a line that empties the stack
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("5").is_ok());
+    assert!(calc.process_line("c").is_ok());
+    assert!(calc.process_line("a").is_ok());
+    assert!(stack_equals(&calc.stack, &[5.0]));
+
+    // Clear on empty: the answer is forgotten
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"clear\" ansmode").is_ok());
+    assert_eq!(calc.answer_mode, AnswerMode::ClearOnEmpty);
+    assert!(calc.process_line("5").is_ok());
+    assert!(calc.process_line("c").is_ok());
+    assert_eq!(calc.last_answer, None);
+    assert!(calc.process_line("a").is_err());
+
+    // ...except for the number softclear keeps on purpose
+    assert!(calc.process_line("7 softclear").is_ok());
+    assert_eq!(calc.last_answer, Some(7.0));
+
+    assert!(calc.process_line("\"never\" ansmode").is_err());
+}
+
+#[test]
+fn test_sink_and_float() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 2 3 sink").is_ok());
+    assert!(stack_equals(&calc.stack, &[3.0, 1.0, 2.0]));
+    assert!(calc.process_line("float").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0, 2.0, 3.0]));
+
+    assert!(calc.process_line("float").is_ok());
+    assert!(stack_equals(&calc.stack, &[2.0, 3.0, 1.0]));
+    assert!(calc.process_line("sink").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0, 2.0, 3.0]));
+
+    calc.stack.clear();
+    assert!(calc.process_line("sink").is_err());
+    assert!(calc.process_line("float").is_err());
+}
+
+#[test]
+fn test_parse_number() {
+    assert_eq!(parse_number("1,234.5"), Some(1234.5));
+    assert_eq!(parse_number("۱۲۳"), Some(123.0));
+    assert_eq!(parse_number("-2e3"), Some(-2000.0));
+    assert_eq!(parse_number("1:30"), Some(90.0));
+    assert_eq!(parse_number("sqrt"), None);
+    assert_eq!(parse_number("\"5\""), None);
+}
+
+#[test]
+fn test_session_summary() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("session").is_err());
+
+    assert!(calc.process_line("1 1 +").is_ok());
+    assert!(calc.process_line("c 10").is_ok());
+    assert!(calc.process_line("c 3 -").is_err());
+    assert!(calc.process_line("c -3").is_ok());
+    // Display-only lines are not new results
+    assert!(calc.process_line("hex").is_ok());
+    assert!(calc.process_line("session").is_ok());
+
+    assert_eq!(calc.all_answers, vec![2.0, 10.0, -3.0]);
+    assert_eq!(
+        session_summary(&calc.all_answers).unwrap(),
+        "3 results: sum 9, mean 3, min -3, max 10"
+    );
+    assert!(stack_equals(&calc.stack, &[-3.0]));
+}
+
+#[test]
+fn test_pack_unpack() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("1 2 3 3 \"grp\" pack").is_ok());
+    assert!(calc.stack.is_empty());
+    assert_eq!(calc.list_storage.get("grp"), Some(&vec![1.0, 2.0, 3.0]));
+    assert!(calc.process_line("\"grp\" unpack").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0, 2.0, 3.0]));
+
+    // Only the top n numbers are packed
+    assert!(calc.process_line("2 \"top\" pack").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0]));
+
+    // Unknown key and too few numbers (Error), stack unchanged
+    assert!(calc.process_line("\"nope\" unpack").is_err());
+    calc.stack.clear();
+    assert!(calc.process_line("1 2 3 \"grp\" pack").is_err());
+    assert_eq!(calc.stack.len(), 4);
+
+    // Packed lists and saved stacks do not overwrite each other
+    calc.stack.clear();
+    assert!(
+        calc.process_line("7 8 \"x\" savestack c 9 1 \"x\" pack")
+            .is_ok()
+    );
+    assert!(calc.process_line("\"x\" loadstack").is_ok());
+    assert!(stack_equals(&calc.stack, &[7.0, 8.0]));
+    assert!(calc.process_line("c \"x\" unpack").is_ok());
+    assert!(stack_equals(&calc.stack, &[9.0]));
+}
+
+#[test]
+fn test_sort_total_order() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("\"k\" 3 1 2 sort").is_ok());
+    assert_eq!(
+        calc.stack,
+        vec![
+            StackItem::Key("k".to_string()),
+            StackItem::Number(1.0),
+            StackItem::Number(2.0),
+            StackItem::Number(3.0)
+        ]
+    );
+    calc.stack.clear();
+
+    // NaN and the infinities land in a fixed place
+    assert!(calc.process_line("nan 1 -inf 0 inf sort").is_ok());
+    assert_eq!(
+        format!("{:?}", calc.stack),
+        format!(
+            "{:?}",
+            [f64::NEG_INFINITY, 0.0, 1.0, f64::INFINITY, f64::NAN].map(StackItem::Number)
+        )
+    );
+    calc.stack.clear();
+
+    assert!(calc.process_line("sort").is_err());
+}
+
+#[test]
+fn test_min_max_normalize() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("\"k\" 0 5 10 minmaxnorm").is_ok());
+    assert_eq!(
+        calc.stack,
+        vec![
+            StackItem::Key("k".to_string()),
+            StackItem::Number(0.0),
+            StackItem::Number(0.5),
+            StackItem::Number(1.0)
+        ]
+    );
+    calc.stack.clear();
+
+    // All equal: everything maps to 0
+    assert!(calc.process_line("3 3 3 minmaxnorm").is_ok());
+    assert!(stack_equals(&calc.stack, &[0.0, 0.0, 0.0]));
+    calc.stack.clear();
+
+    assert!(calc.process_line("minmaxnorm").is_err());
+}
+
+#[test]
+fn test_zscore() {
+    let mut calc = Calculator::new();
+
+    // mean 5, sample standard deviation sqrt(32 / 7)
+    assert!(calc.process_line("\"k\" 2 4 4 4 5 5 7 9 zscore").is_ok());
+    assert_eq!(calc.stack[0], StackItem::Key("k".to_string()));
+    let s = (32.0f64 / 7.0).sqrt();
+    let expected = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].map(|x| (x - 5.0) / s);
+    for (item, want) in calc.stack[1..].iter().zip(expected) {
+        match item {
+            StackItem::Number(got) => assert!((got - want).abs() < 1e-12),
+            _ => panic!("expected a number"),
+        }
+    }
+    calc.stack.clear();
+
+    // Zero standard deviation and a single value (Error), values restored
+    assert!(calc.process_line("3 3 3 zscore").is_err());
+    assert!(stack_equals(&calc.stack, &[3.0, 3.0, 3.0]));
+    calc.stack.clear();
+    assert!(calc.process_line("3 zscore").is_err());
+    assert!(stack_equals(&calc.stack, &[3.0]));
+}
+
+#[test]
+fn test_help_examples() {
+    let plus = help_details("+", AngleMode::Radians).unwrap();
+    assert!(plus.contains("Usage: a b + | Addition"));
+    assert!(plus.contains("Example: 3 4 + -> 7"));
+
+    // Operators without an example show just their type and usage
+    assert_eq!(help_example("floor"), None);
+    assert_eq!(
+        help_details("floor", AngleMode::Radians).unwrap(),
+        "  Type: Rounding\n  Usage: a floor | Floor (rounds down)"
+    );
+    assert_eq!(help_details("bogus", AngleMode::Radians), None);
+
+    // Every example gives the result it shows
+    for info in operators() {
+        if let Some(example) = help_example(info.token) {
+            let (input, output) = example.split_once(" -> ").unwrap();
+            let mut calc = Calculator::new();
+            assert!(calc.process_line(input).is_ok(), "{}", example);
+            let shown: Vec<String> = calc.stack.iter().map(format_item).collect();
+            assert_eq!(shown.join(" "), output, "{}", example);
+        }
+    }
+}
+
+#[test]
+fn test_cmp() {
+    let mut calc = Calculator::new();
+    for (line, expected) in [
+        ("3 5 cmp", -1.0),
+        ("5 3 cmp", 1.0),
+        ("4 4 cmp", 0.0),
+        // Total order: NaN is above everything, -0 is below 0
+        ("nan inf cmp", 1.0),
+        ("inf nan cmp", -1.0),
+        ("-0 0 cmp", -1.0),
+    ] {
+        assert!(calc.process_line(line).is_ok());
+        assert!(stack_equals(&calc.stack, &[expected]), "{}", line);
+        calc.stack.clear();
+    }
+}
+
+#[test]
+fn test_step_descriptions() {
+    let num = StackItem::Number;
+    assert_eq!(
+        describe_effect(&[num(3.0), num(4.0)], &[num(7.0)]),
+        "popped 3 and 4, pushed 7"
+    );
+    assert_eq!(
+        describe_effect(&[num(1.0), num(2.0), num(3.0)], &[]),
+        "popped 1, 2 and 3"
+    );
+    assert_eq!(describe_effect(&[], &[num(1234.5)]), "pushed 1,234.5");
+    assert_eq!(describe_effect(&[], &[]), "stack unchanged");
+
+    // Step mode does not change the results
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("step 3 0 + 2 <>").is_ok());
+    assert!(calc.step);
+    assert!(stack_equals(&calc.stack, &[2.0, 3.0]));
+}
+
+#[test]
+fn test_checkpoint_restore() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("7 \"x\" sto 1 2 +").is_ok());
+    assert!(calc.process_line("\"a\" checkpoint").is_ok());
+    let saved = calc.checkpoints.get("a").unwrap().clone();
+    assert!(stack_equals(&saved.stack, &[3.0]));
+
+    assert!(
+        calc.process_line("c 99 \"x\" sto 5 \"y\" sto 42 10 *")
+            .is_ok()
+    );
+    assert!(calc.process_line("\"a\" restore").is_ok());
+    assert_eq!(
+        CalculatorState {
+            stack: calc.stack.clone(),
+            storage: calc.storage.clone(),
+            last_answer: calc.last_answer,
+        },
+        saved
+    );
+
+    // Unknown name (Error), key left on the stack
+    assert!(calc.process_line("\"b\" restore").is_err());
+    assert_eq!(calc.stack.last(), Some(&StackItem::Key("b".to_string())));
+}
+
+#[test]
+fn test_between() {
+    let mut calc = Calculator::new();
+    for (line, expected) in [
+        ("5 0 10 between", 1.0),
+        ("15 0 10 between", 0.0),
+        ("0 0 10 between", 1.0),
+        ("10 0 10 between", 1.0),
+    ] {
+        assert!(calc.process_line(line).is_ok());
+        assert!(stack_equals(&calc.stack, &[expected]), "{}", line);
+        calc.stack.clear();
+    }
+
+    // lo > hi (Error), operands restored
+    assert!(calc.process_line("5 10 0 between").is_err());
+    assert!(stack_equals(&calc.stack, &[5.0, 10.0, 0.0]));
+}
+
+#[test]
+fn test_join() {
+    assert_eq!(join_numbers(&[1.0, 2.0, 3.0], ", "), "1, 2, 3");
+    assert_eq!(join_numbers(&[1.5, -2.0], "\\t"), "1.5\t-2");
+    assert_eq!(join_numbers(&[1234.0], ";"), "1,234");
+
+    // Only the separator is popped; the run stops at the first key
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"k\" 1 2 3 \", \" join").is_ok());
+    assert_eq!(calc.stack.len(), 4);
+    assert!(calc.process_line("\";\" join").is_ok());
+    assert_eq!(calc.stack.len(), 4);
+
+    calc.stack.clear();
+    assert!(calc.process_line("\",\" join").is_err());
+}
+
+#[test]
+fn test_statistical_mode() {
+    assert_eq!(statistical_mode(&[1.0, 2.0, 2.0, 3.0]), Some(2.0));
+    assert_eq!(statistical_mode(&[2.0, 2.0, 1.0, 1.0]), Some(1.0));
+    assert_eq!(statistical_mode(&[5.0]), Some(5.0));
+    assert_eq!(statistical_mode(&[]), None);
+
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("\"k\" 1 2 2 3 mode").is_ok());
+    assert_eq!(calc.stack.len(), 2);
+    assert_eq!(get_number_at_top(&calc.stack), 2.0);
+    calc.stack.clear();
+    assert!(calc.process_line("1 1 2 2 mode").is_ok());
+    assert!(stack_equals(&calc.stack, &[1.0]));
+    calc.stack.clear();
+    assert!(calc.process_line("mode").is_err());
+}
+
+#[test]
+fn test_logb() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("100 10 logb").is_ok());
+    assert!(stack_equals(&calc.stack, &[2.0]));
+    calc.stack.clear();
+
+    // Invalid inputs (Error), operands restored
+    for (line, err) in [
+        ("8 1 logb", "logb: base cannot be 1"),
+        ("8 0 logb", "logb: base must be positive"),
+        ("8 -2 logb", "logb: base must be positive"),
+        ("-5 2 logb", "logb: argument must be positive"),
+        ("0 2 logb", "logb: argument must be positive"),
+    ] {
+        assert_eq!(calc.process_line(line), Err(err), "{}", line);
+        assert_eq!(calc.stack.len(), 2);
+        calc.stack.clear();
+    }
+}
+
+#[test]
+fn test_count_compare() {
+    let mut calc = Calculator::new();
+
+    assert!(calc.process_line("50 120 200 100 countgt").is_ok());
+    assert!(stack_equals(&calc.stack, &[50.0, 120.0, 200.0, 2.0]));
+    calc.stack.clear();
+
+    assert!(calc.process_line("\"k\" 50 120 200 120 countlt").is_ok());
+    assert_eq!(calc.stack.len(), 5);
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+    calc.stack.clear();
+
+    assert!(calc.process_line("3 1 3 3 counteq").is_ok());
+    assert!(stack_equals(&calc.stack, &[3.0, 1.0, 3.0, 2.0]));
+    calc.stack.clear();
+
+    assert!(calc.process_line("countgt").is_err());
+}
+
+#[test]
+fn test_zipwith() {
+    let mut calc = Calculator::new();
+
+    // Pairs (10, 1) and (20, 2): the deeper block is the left operand
+    assert!(calc.process_line("10 20 1 2 2 \"+\" zipwith").is_ok());
+    assert!(stack_equals(&calc.stack, &[11.0, 22.0]));
+    calc.stack.clear();
+    assert!(calc.process_line("5 10 20 1 2 2 \"-\" zipwith").is_ok());
+    assert!(stack_equals(&calc.stack, &[5.0, 9.0, 18.0]));
+    calc.stack.clear();
+
+    // Unary operator and too few numbers (Error), stack unchanged
+    assert!(calc.process_line("1 2 1 \"sqrt\" zipwith").is_err());
+    assert_eq!(calc.stack.len(), 4);
+    calc.stack.clear();
+    assert!(calc.process_line("1 2 3 2 \"+\" zipwith").is_err());
+    assert_eq!(calc.stack.len(), 5);
+    calc.stack.clear();
+    assert!(calc.process_line("1 2 1e19 \"+\" zipwith").is_err());
+    assert_eq!(calc.stack.len(), 4);
+}
+
+#[test]
+fn test_explain() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("5 3 +").is_ok());
+    assert_eq!(calc.explain().unwrap(), "5 3 + = 8");
+
+    // Histories nest, and survive stack shuffles and read-only commands
+    assert!(calc.process_line("2 * 1 <> hex").is_ok());
+    assert_eq!(calc.explain().unwrap(), "5 3 + 2 * = 16");
+    assert!(calc.process_line("explain").is_ok());
+
+    // A result equal to one of its operands is still a new result
+    assert!(calc.process_line("c 2 0 +").is_ok());
+    assert_eq!(calc.explain().unwrap(), "2 0 + = 2");
+    assert!(calc.process_line("c 5 abs").is_ok());
+    assert_eq!(calc.explain().unwrap(), "5 abs = 5");
+
+    // Typed numbers and values from storage have no history
+    assert!(calc.process_line("\"x\" sto 4").is_ok());
+    assert!(
+        calc.explain()
+            .unwrap()
+            .starts_with("4 has no recorded history")
+    );
+    assert!(calc.process_line("c").is_ok());
+    assert_eq!(calc.explain(), None);
+    assert!(calc.process_line("explain").is_err());
+}
+
+#[test]
+fn test_factratio() {
+    let mut calc = Calculator::new();
+    assert!(calc.process_line("100 98 factratio").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 9900.0);
+    assert!(calc.process_line("c 5 5 factratio").is_ok());
+    assert_eq!(get_number_at_top(&calc.stack), 1.0);
+
+    // Invalid input restores both operands
+    for line in [
+        "c 3 5 factratio",
+        "c -1 0 factratio",
+        "c 5.5 2 factratio",
+        "c 1000 0 factratio",
+        "c 2e19 2e19 factratio",
+    ] {
+        assert!(calc.process_line(line).is_err(), "{line}");
+        assert_eq!(calc.stack.len(), 2, "{line}");
+    }
+}
+
+#[test]
+fn test_digitsum_digitalroot() {
+    assert_eq!(digit_sum(0), 0);
+    assert_eq!(digit_sum(12345), 15);
+    assert_eq!(digital_root(0), 0);
+    assert_eq!(digital_root(9), 9);
+    assert_eq!(digital_root(99999), 9);
+
+    let mut calc = Calculator::new();
+    for (line, expected) in [
+        ("12345 digitsum", 15.0),
+        ("12345 digitalroot", 6.0),
+        ("-12345.9 digitsum", 15.0),
+        ("-987 digitalroot", 6.0),
+    ] {
+        assert!(calc.process_line(line).is_ok(), "{line}");
+        assert_eq!(get_number_at_top(&calc.stack), expected, "{line}");
+    }
+
+    for line in [
+        "c inf digitsum",
+        "c nan digitalroot",
+        "c 1e300 digitsum",
+        "c -1e19 digitalroot",
+    ] {
+        assert!(calc.process_line(line).is_err(), "{line}");
+        assert_eq!(calc.stack.len(), 1, "{line}");
+    }
+}